@@ -3,62 +3,86 @@
 mod utils;
 mod pokemon_counter;
 
-use eframe::epaint::Vec2;
-use inputbot::KeybdKey;
-use std::thread;
-use utils::cli_handler::{handle_input, increment, run_cli, State};
-use utils::db_handler::{connect, get_all_counters};
+use clap::Parser;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use utils::arg_handler::{Args, Command};
+use utils::config_handler::{load_config, locate_database};
+use utils::cli_handler::{read_input, run_cli, run_command, run_hotkey, State};
+use utils::db_handler::CounterStore;
+use utils::hotkey_handler::{bind_global, listen, Binding, Press};
+use utils::odds_handler::load_rules;
 use pokemon_counter::{PokemonCounter, MIN_WINDOW_SIZE};
 use eframe::{run_native, NativeOptions};
 
-fn main() {
-    let res = connect();
-    match res {
-        Ok(_) => {}
-        Err(_) => {
-            println!("Could not create/connect to db. Contact developer.")
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let path = match locate_database(args.db) {
+        Ok(path) => path,
+        Err(err) => {
+            println!("Could not prepare the database location: {}", err);
+            return ExitCode::FAILURE;
         }
-    }
-    let cli = false;
-
-    if cli {
-        println!("Welcome to the counter app!");
-        println!("Type help to get list of commands.");
-        let mut id = -1;
-        let mut state: State = State::Cont;
+    };
 
-        while state != State::Exit {
-            state = cliruntime(&id);
-            if let State::Load(next) = state { id = next } // destructures but ignores errors
+    let mut store = match CounterStore::open(&path) {
+        Ok(store) => store,
+        Err(err) => {
+            println!("Could not create/connect to db {:?}. {}", path, err);
+            return ExitCode::FAILURE;
         }
-    } else {
-        let pokemons = match get_all_counters() {
-            Ok(r) => r,
-            Err(_) => {
-                println!("Failed");
-                panic!("Failed");
+    };
+
+    match args.command.unwrap_or(Command::Gui) {
+        Command::Repl => {
+            println!("Welcome to the counter app!");
+            println!("Type help to get list of commands.");
+            let store = Arc::new(Mutex::new(store));
+            let bindings = load_config().repl_bindings;
+            let mut id = -1;
+            let mut state: State = State::Cont;
+
+            while state != State::Exit {
+                state = cliruntime(&store, &id, &bindings);
+                if let State::Load(next) = state { id = next } // destructures but ignores errors
             }
-        };
-        let mut win_option = NativeOptions::default();
-        win_option.min_window_size = Some(MIN_WINDOW_SIZE);
+        }
+        Command::Counter(command) => return run_command(&mut store, &command),
+        Command::Gui => {
+            let pokemons = match store.get_all_counters() {
+                Ok(r) => r,
+                Err(err) => {
+                    println!("Could not load counters. {}", err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let win_option = NativeOptions {
+                min_window_size: Some(MIN_WINDOW_SIZE),
+                ..Default::default()
+            };
+            let rules = load_rules();
+            let bindings = load_config().bindings;
 
-        run_native("Pokemon Counter", win_option,Box::new(|cc| Box::new(PokemonCounter::new(cc, pokemons))));
+            run_native("Pokemon Counter", win_option,Box::new(|cc| Box::new(PokemonCounter::new(cc, store, pokemons, rules, bindings))));
+        }
     }
+    ExitCode::SUCCESS
 }
 
-fn cliruntime(current_id: &i32) -> State {
+fn cliruntime(store: &Arc<Mutex<CounterStore>>, current_id: &i32, bindings: &[Binding]) -> State {
     let id = *current_id;
-    let clonedid = id;
-    thread::spawn(move || {
-        KeybdKey::ScrollLockKey.unbind();
-        KeybdKey::ScrollLockKey.block_bind(move || {
-            let id2 = clonedid;
-            println!();
-            increment(id2);
-        });
-
-        inputbot::handle_input_events();
+    let hotkey_store = Arc::clone(store);
+    bind_global(bindings, move |binding, press| {
+        println!();
+        if press != Press::Accepted {
+            println!("Ignored {} press of {}.", press.label(), binding.key_label());
+            return;
+        }
+        if let Ok(mut store) = hotkey_store.lock() {
+            run_hotkey(&mut store, id, binding);
+        }
     });
+    listen();
 
-    run_cli!(id)
-}
\ No newline at end of file
+    run_cli!(store, id)
+}
@@ -1,97 +1,92 @@
-use crate::utils::db_handler::{
-    add_new_counter, get_row_id, get_sprite, add_counter, read_counter, save_sprite, Pokemon,
+use crate::utils::db_handler::{now_millis, image_content_type, CounterStore, DBError, HuntSettings, HuntStatus, OddsChange, Pokemon, Source, Sprite, format_date};
+use crate::utils::db_handler;
+use crate::utils::config_handler::{load_config, save_config};
+use crate::utils::hotkey_handler::{self, default_bindings, Action, Binding, Press, PressFilter};
+use crate::utils::odds_handler::{GameRules, OddsRules, Progress};
+use crate::utils::prob_handler::{
+    num_tries_for_x_percent_chance, convert_to_percentage, expected_encounters, median_encounters,
+    luck_percentile, probability_at_least_k, probability_with_changing_odds, encounters_for_chance,
+    chance_curve,
 };
-use crate::utils::prob_handler::{num_tries_for_x_percent_chance, probability_within_n_tries,convert_to_percentage};
 use eframe::egui::Grid;
-use eframe::egui::{self, CentralPanel, Response, ScrollArea, Ui, TopBottomPanel, Context, Button, Sense, SidePanel, containers::{Frame}};
+use eframe::egui::plot::{Legend, Line, MarkerShape, Plot, PlotPoint, PlotPoints, Points, Text, VLine};
+use eframe::egui::{self, Align2, ComboBox, DragValue, Event, Key, Modifiers, Window, CentralPanel, Response, ScrollArea, Ui, TopBottomPanel, Context, Button, Sense, SidePanel, containers::{Frame}};
 use eframe::App;
 use eframe::epaint::{Color32, Vec2};
 use egui_extras::RetainedImage;
-use std::collections::HashSet;
-use std::sync::mpsc::{self, SyncSender, Receiver};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver};
 use std::thread;
-use inputbot::KeybdKey;
+use std::time::Instant;
 
-const GEN_2_TO_5_ODDS: f64 = 8192.0;
-const GEN_6_PLUS_ODDS: f64 = 4096.0;
-const EGUI_KEY: egui::Key = egui::Key::Home;
-const KEYDB_KEY: KeybdKey = KeybdKey::HomeKey;
-const KEY_NAME: &str = "HOME";
 const COUNT_BUTTON_SIZE: [f32;2] = [120.0,50.0];
+const MAX_STEP: i32 = 1000;
+const MAX_DEBOUNCE_MS: u64 = 2000;
+const MAX_RATE: u32 = 50;
+const MAX_SEARCH_LEVEL: u32 = 999;
+const CHART_THRESHOLDS: [f64; 4] = [0.5, 0.75, 0.9, 0.99];
+const CHART_SAMPLES: i32 = 200;
+const MIN_CHART_HEIGHT: f32 = 150.0;
+const GRID_COLUMNS: usize = 3;
+const GRID_SPRITE_SIZE: Vec2 = Vec2::new(64.0, 64.0);
+const GRID_MAX_HEIGHT: f32 = 280.0;
 
 pub const MIN_WINDOW_SIZE: Vec2 = Vec2::new(688.0, 524.0);
 
+/// A finished sprite download: the counter it was started for, the name it fetched and the result.
+type SpriteDownload = (i32, String, Result<Sprite, String>);
+
 pub struct PokemonCounter {
+    store: CounterStore,
+    /// Active hunts, shown in the side panel.
     pokemons: Vec<Pokemon>,
+    /// Found and abandoned hunts, shown in the history window.
+    archive: Vec<Pokemon>,
+    show_history: bool,
+    /// Shows every active counter at once instead of just the current one.
+    grid_view: bool,
+    /// Sprites by counter name. Holds the unknown sprite while a download is running.
+    sprites: HashMap<String, Option<RetainedImage>>,
+    sprite_sender: Sender<SpriteDownload>,
+    sprite_receiver: Receiver<SpriteDownload>,
     current_idx: usize,
     name_field: String,
+    add_amount: i32,
+    set_value: i32,
+    chain_value: i32,
+    confirm_reset: bool,
+    /// Id of the counter being renamed and the name typed so far.
+    renaming: Option<(i32, String)>,
+    /// Id of the counter waiting for its deletion to be confirmed.
+    confirm_delete: Option<i32>,
     error_message: String,
-    msg_receiver: Receiver<i32>,
-    sel_gen: Generation,
-    gen4_mod: Gen4Modifiers,
-    gen5_mod: Gen5Modifiers,
-    gen6_mod: Gen6Modifiers,
-    gen7_mod: Gen7Modifiers,
-    gen8_mod: Gen8Modifiers,
+    msg_sender: SyncSender<(Binding, Press)>,
+    msg_receiver: Receiver<(Binding, Press)>,
+    bindings: Vec<Binding>,
+    /// Debounce state of presses made inside the window, one per binding.
+    press_filters: Vec<PressFilter>,
+    ignored_presses: u32,
+    last_ignored: String,
+    /// Bindings being edited; global hotkeys are paused while this is open.
+    key_settings: Option<KeySettings>,
+    /// The counter whose saved game and methods are currently selected.
+    settings_for: Option<i32>,
+    rules: OddsRules,
+    settings: HuntSettings,
+    /// Odds changes of the counter in `settings_for`.
+    odds_history: Vec<OddsChange>,
     odds: f64,
     cur_odds: f64
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub enum Generation {
-    Gen2or3,
-    Gen4,
-    Gen5,
-    Gen6,
-    Gen7,
-    Gen8,
-}
-
-#[derive(Debug, Eq, PartialEq, Default)]
-pub struct Gen4Modifiers {
-    masuda: bool,
-    radar: bool,
-}
-
-#[derive(Debug, Eq, PartialEq, Default)]
-pub struct Gen5Modifiers {
-    masuda: bool,
-    charm: bool,
+struct KeySettings {
+    bindings: Vec<Binding>,
+    /// The binding waiting for a key press.
+    capturing: Option<usize>,
 }
 
-#[derive(Debug, Eq, PartialEq, Default)]
-pub struct Gen6Modifiers {
-    masuda: bool,
-    radar: bool,
-    charm: bool,
-    safari: bool,
-    fishing: bool,
-    hidden: bool,
-}
-
-#[derive(Debug, Eq, PartialEq, Default)]
-pub struct Gen7Modifiers {
-    masuda: bool,
-    charm: bool,
-    sos: bool,
-}
-
-#[derive(Debug, Eq, PartialEq, Default)]
-pub struct Gen8Modifiers {
-    masuda: bool,
-    charm: bool,
-    radar: bool,
-    dynamax: bool,
-    underground: bool,
-    mass: bool,
-    massive: bool,
-    dex10: bool,
-    dexmax: bool
-}
-
-
 impl App for PokemonCounter {
-    fn update(&mut self, ctx: &eframe::egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
         TopBottomPanel::bottom("footer").show(ctx, |ui|{
             ui.vertical_centered(|ui| {
                 ui.add_space(10.);
@@ -99,137 +94,872 @@ impl App for PokemonCounter {
             });
         });
         SidePanel::left("Pokemonlist").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Hunt history").clicked() {
+                    self.show_history = !self.show_history;
+                }
+                if ui.button("Key bindings").clicked() && self.key_settings.is_none() {
+                    self.open_key_settings();
+                }
+            });
+            ui.checkbox(&mut self.grid_view, "Grid view");
+            ui.separator();
+            let mut abandon = None;
+            let mut link = None;
+            let mut bind_key = None;
             ScrollArea::vertical().show(ui, |ui| {
                 for (i, pkmn) in self.pokemons.iter().enumerate() {
-                    if ui
-                        .button(format!("{:?}: {:?}", &pkmn.name, &pkmn.counter))
-                        .clicked()
-                    {
+                    let linked = if pkmn.linked { "🔗 " } else { "" };
+                    let button = ui.button(format!("{}{:?}: {:?}", linked, &pkmn.name, &pkmn.counter));
+                    if button.clicked() {
                         self.current_idx = i;
                     };
+                    button.context_menu(|ui| {
+                        if ui.button("Rename").clicked() {
+                            self.renaming = Some((pkmn.id, pkmn.name.clone()));
+                            ui.close_menu();
+                        }
+                        if ui.button(if pkmn.linked { "Unlink" } else { "Link" }).clicked() {
+                            link = Some((i, !pkmn.linked));
+                            ui.close_menu();
+                        }
+                        if ui.button("Bind a key…").clicked() {
+                            bind_key = Some(pkmn.name.clone());
+                            ui.close_menu();
+                        }
+                        if ui.button("Abandon hunt").clicked() {
+                            abandon = Some(i);
+                            ui.close_menu();
+                        }
+                        if ui.button("Delete").clicked() {
+                            self.confirm_delete = Some(pkmn.id);
+                            ui.close_menu();
+                        }
+                    });
                 }
             });
+            if let Some(i) = abandon {
+                self.finish_hunt(i, HuntStatus::Abandoned);
+            }
+            if let Some((i, linked)) = link {
+                self.set_linked(i, linked);
+            }
+            if let Some(name) = bind_key {
+                self.bind_counter_key(name);
+            }
         });
         CentralPanel::default().show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                self.counter_button(ui);
-                self.current_label(ui);
-                self.current_sprite(ui);
-            });
+            if self.grid_view {
+                ui.horizontal(|ui| {
+                    self.counter_button(ui);
+                    let targets = self.press_targets().len();
+                    if targets > 1 {
+                        ui.label(format!("Counts on {} linked counters", targets));
+                    }
+                });
+                self.counter_grid(ui);
+            } else {
+                ui.horizontal(|ui| {
+                    self.counter_button(ui);
+                    self.current_label(ui);
+                    self.current_sprite(ui);
+                });
+            }
+            self.counter_controls(ui);
 
-            ui.label(format!("Current hotkey: '{}'", KEY_NAME));
+            ui.label(format!("Increment hotkey: {}. Ctrl+Z to undo, Ctrl+Y to redo.", hotkey_handler::describe(&self.bindings, Action::Increment)));
+            self.ignored_presses_label(ui);
             self.add_new_text_field(ui);
+            self.load_hunt_settings();
             self.select_generation_dropdown(ui);
             self.odds_calculator(ui);
+            self.save_hunt_settings();
         });
-        if ctx.input().key_pressed(EGUI_KEY) {
-            self.update_counter(1);
+        self.reset_dialog(ctx);
+        self.rename_dialog(ctx);
+        self.delete_dialog(ctx);
+        self.history_window(ctx);
+        self.key_settings_window(ctx);
+        self.handle_key_bindings(ctx);
+        if !ctx.wants_keyboard_input() {
+            let (undo, redo) = {
+                let mut input = ctx.input_mut();
+                (input.consume_key(Modifiers::COMMAND, Key::Z), input.consume_key(Modifiers::COMMAND, Key::Y))
+            };
+            if undo { self.step_history(true); }
+            if redo { self.step_history(false); }
         }
-        self.receive_increments();
+        self.receive_hotkeys(ctx);
+        self.receive_sprites();
     }
 }
 
 impl PokemonCounter {
-    pub fn new(cc: &eframe::CreationContext<'_>, pokemons: Vec<Pokemon>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>, store: CounterStore, counters: Vec<Pokemon>, rules: OddsRules, bindings: Vec<Binding>) -> Self {
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
         // for e.g. egui::PaintCallback.
         let (tx, rx) = mpsc::sync_channel(10);
         let c = cc.egui_ctx.clone();
-        PokemonCounter::spawn_input_bot_thread(c, tx, KEYDB_KEY);
+        PokemonCounter::spawn_input_bot_thread(c, tx.clone(), &bindings);
+        let (pokemons, archive) = counters.into_iter()
+            .partition(|p| p.status == HuntStatus::Active);
+        let (sprite_sender, sprite_receiver) = mpsc::channel();
 
         Self {
+            store,
             pokemons,
+            archive,
+            show_history: false,
+            grid_view: false,
+            sprites: HashMap::new(),
+            sprite_sender,
+            sprite_receiver,
             current_idx: 0,
             name_field: "".to_owned(),
+            add_amount: 1,
+            set_value: 0,
+            chain_value: 0,
+            confirm_reset: false,
+            renaming: None,
+            confirm_delete: None,
             error_message: "".to_owned(),
+            msg_sender: tx,
             msg_receiver: rx,
-            sel_gen: Generation::Gen2or3,
-            gen4_mod: Gen4Modifiers::default(),
-            gen5_mod: Gen5Modifiers::default(),
-            gen6_mod: Gen6Modifiers::default(),
-            gen7_mod: Gen7Modifiers::default(),
-            gen8_mod: Gen8Modifiers::default(),
+            press_filters: bindings.iter().map(|_| PressFilter::default()).collect(),
+            bindings,
+            ignored_presses: 0,
+            last_ignored: String::new(),
+            key_settings: None,
+            settings_for: None,
+            rules,
+            settings: HuntSettings::default(),
+            odds_history: vec![],
             odds: 0.0,
             cur_odds: 0.0,
         }
     }
 
-    fn spawn_input_bot_thread(c: Context, tx: SyncSender<i32>, key: KeybdKey) {
-        thread::spawn(move || {
-            key.unbind();
-            key.block_bind(move || {
-                match tx.send(1) {
-                    Ok(_) => {
-                        println!("Sending succeeded");
-                        c.request_repaint();
-                    },
-                    Err(_) => println!("Failed sending"),
-                };
-                println!("Pressed");
-            });
-            inputbot::handle_input_events();
+    fn spawn_input_bot_thread(c: Context, tx: SyncSender<(Binding, Press)>, bindings: &[Binding]) {
+        hotkey_handler::bind_global(bindings, move |binding, press| {
+            match tx.send((binding.clone(), press)) {
+                Ok(_) => c.request_repaint(),
+                Err(_) => println!("Failed sending"),
+            };
+        });
+        hotkey_handler::listen();
+    }
+
+    /// Handles every global press sent since the last frame. Keys the focused
+    /// window also sees are left to `handle_key_bindings`, so they count once.
+    fn receive_hotkeys(&mut self, ctx: &Context) {
+        let focused = ctx.input().raw.has_focus;
+        while let Ok((binding, press)) = self.msg_receiver.try_recv() {
+            if focused && hotkey_handler::reaches_window(&binding.key) {
+                continue;
+            }
+            self.handle_press(&binding, press);
+        }
+    }
+
+    fn handle_press(&mut self, binding: &Binding, press: Press) {
+        if press == Press::Accepted {
+            self.run_binding(binding);
+        } else {
+            self.ignored_presses += 1;
+            self.last_ignored = format!("{} ({})", binding.key_label(), press.label());
+        }
+    }
+
+    fn ignored_presses_label(&mut self, ui: &mut Ui) {
+        if self.ignored_presses == 0 { return; }
+        ui.horizontal(|ui| {
+            ui.colored_label(Color32::YELLOW, format!("Ignored {} hotkey press(es), last: {}", self.ignored_presses, self.last_ignored))
+                .on_hover_text("Presses inside a binding's debounce window or over its rate limit are not counted.");
+            if ui.small_button("Dismiss").clicked() {
+                self.ignored_presses = 0;
+            }
         });
     }
 
-    fn receive_increments(&mut self) {
-        let val = match self.msg_receiver.try_recv() {
-            Ok(v) => v,
-            Err(_) => return,
+    /// Runs the bindings whose keys were pressed while the window has focus.
+    fn handle_key_bindings(&mut self, ctx: &Context) {
+        if self.key_settings.is_some() || ctx.wants_keyboard_input() {
+            return;
+        }
+        let pressed = {
+            let input = ctx.input();
+            self.bindings.iter()
+                .enumerate()
+                .filter(|(_, b)| b.matches_modifiers(input.modifiers.shift, input.modifiers.ctrl))
+                .filter(|(_, b)| hotkey_handler::egui_key(&b.key).is_some_and(|k| input.key_pressed(k)))
+                .map(|(i, b)| (i, b.clone()))
+                .collect::<Vec<_>>()
         };
-        self.update_counter(val);
+        let now = Instant::now();
+        for (i, binding) in &pressed {
+            let press = self.press_filters.get_mut(*i).map_or(Press::Accepted, |f| f.check(binding, now));
+            self.handle_press(binding, press);
+        }
     }
 
-    fn was_submitted(re: &Response) -> bool {
-        re.lost_focus() && re.ctx.input().key_pressed(egui::Key::Enter)
+    fn run_binding(&mut self, binding: &Binding) {
+        match binding.action {
+            Action::Increment => self.press(binding.amount, Source::Hotkey),
+            Action::Decrement => self.press(Some(-binding.amount.unwrap_or(1)), Source::Hotkey),
+            Action::NextCounter => self.cycle_counter(true),
+            Action::PreviousCounter => self.cycle_counter(false),
+            Action::IncrementCounter => {
+                let name = binding.counter.as_deref().unwrap_or_default();
+                match self.pokemons.iter().position(|p| p.name == name) {
+                    Some(idx) => {
+                        let amount = binding.amount.unwrap_or(self.pokemons[idx].step);
+                        self.change_counter(idx, |store, id| store.add_counter(id, amount, Source::Hotkey));
+                    }
+                    None => {self.error_message = format!("No active counter named {:?}.", name);},
+                }
+            }
+            Action::Undo => self.step_history(true),
+        }
+    }
+
+    fn cycle_counter(&mut self, forward: bool) {
+        let len = self.pokemons.len();
+        if len == 0 { return; }
+        self.current_idx = if forward {
+            (self.current_idx + 1) % len
+        } else {
+            (self.current_idx + len - 1) % len
+        };
+    }
+
+    /// Opens the key bindings with a new binding for the counter, waiting for its key.
+    fn bind_counter_key(&mut self, name: String) {
+        if self.key_settings.is_none() {
+            self.open_key_settings();
+        }
+        if let Some(settings) = self.key_settings.as_mut() {
+            settings.bindings.push(Binding { counter: Some(name), ..Binding::new("Home", Action::IncrementCounter) });
+            settings.capturing = Some(settings.bindings.len() - 1);
+        }
+    }
+
+    /// Pauses the global hotkeys so the keys being rebound reach the window.
+    fn open_key_settings(&mut self) {
+        hotkey_handler::set_paused(true);
+        self.key_settings = Some(KeySettings { bindings: self.bindings.clone(), capturing: None });
     }
 
-    fn get_current_mut(&mut self) -> Option<&mut Pokemon> {
-        return self.pokemons.get_mut(self.current_idx);
+    fn close_key_settings(&mut self, ctx: &Context, save: Option<Vec<Binding>>) {
+        self.key_settings = None;
+        if let Some(bindings) = save {
+            let mut config = load_config();
+            config.bindings = bindings.clone();
+            match save_config(&config) {
+                Ok(_) => self.error_message.clear(),
+                Err(err) => {self.error_message = format!("Could not save key bindings. {}", err);},
+            }
+            self.rebind(ctx, bindings);
+        }
+        hotkey_handler::set_paused(false);
+    }
+
+    /// Swaps in new bindings, restarting the global listener if it stopped for lack of keys.
+    fn rebind(&mut self, ctx: &Context, bindings: Vec<Binding>) {
+        PokemonCounter::spawn_input_bot_thread(ctx.clone(), self.msg_sender.clone(), &bindings);
+        hotkey_handler::unbind_unused(&self.bindings, &bindings);
+        self.press_filters = bindings.iter().map(|_| PressFilter::default()).collect();
+        self.bindings = bindings;
+    }
+
+    fn key_settings_window(&mut self, ctx: &Context) {
+        let mut settings = match self.key_settings.take() {
+            Some(s) => s,
+            None => return,
+        };
+        if let Some(i) = settings.capturing {
+            let pressed = ctx.input().events.iter().find_map(|ev| match ev {
+                Event::Key { key, pressed: true, modifiers } => Some((*key, *modifiers)),
+                _ => None,
+            });
+            if let Some((key, modifiers)) = pressed {
+                settings.capturing = None;
+                if key != egui::Key::Escape {
+                    match hotkey_handler::key_name(key) {
+                        Some(name) => {
+                            let binding = &mut settings.bindings[i];
+                            binding.key = name.to_owned();
+                            binding.shift = modifiers.shift;
+                            binding.ctrl = modifiers.ctrl;
+                        }
+                        None => {self.error_message = format!("{:?} cannot be used as a hotkey.", key);},
+                    }
+                }
+            }
+        }
+        let mut open = true;
+        let mut done = None;
+        Window::new("Key bindings")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("Hotkeys are paused while this window is open.");
+                let mut remove = None;
+                Grid::new("bindings-grid").striped(true).show(ui, |ui| {
+                    ui.strong("Action");
+                    ui.strong("Counter");
+                    ui.strong("Amount");
+                    ui.strong("Debounce");
+                    ui.strong("Max/s");
+                    ui.strong("Key");
+                    ui.end_row();
+                    for (i, binding) in settings.bindings.iter_mut().enumerate() {
+                        ComboBox::from_id_source(("binding-action", i))
+                            .selected_text(binding.action.label())
+                            .show_ui(ui, |ui| {
+                                for action in Action::ALL {
+                                    ui.selectable_value(&mut binding.action, action, action.label());
+                                }
+                            });
+                        if binding.action == Action::IncrementCounter {
+                            let selected = binding.counter.clone().unwrap_or_else(|| "Pick a counter".to_owned());
+                            ComboBox::from_id_source(("binding-counter", i))
+                                .selected_text(selected)
+                                .show_ui(ui, |ui| {
+                                    for pkmn in &self.pokemons {
+                                        let is_selected = binding.counter.as_deref() == Some(pkmn.name.as_str());
+                                        if ui.selectable_label(is_selected, &pkmn.name).clicked() {
+                                            binding.counter = Some(pkmn.name.clone());
+                                        }
+                                    }
+                                });
+                        } else {
+                            binding.counter = None;
+                            ui.label("-");
+                        }
+                        if matches!(binding.action, Action::Increment | Action::Decrement | Action::IncrementCounter) {
+                            // 0 stands for the default: the counter's step, or one when decrementing.
+                            let mut amount = binding.amount.unwrap_or(0);
+                            let default = if binding.action == Action::Decrement { "1" } else { "step" };
+                            ui.add(DragValue::new(&mut amount)
+                                .clamp_range(0..=MAX_STEP)
+                                .custom_formatter(|n, _| if n == 0.0 { default.to_owned() } else { format!("{}", n) }));
+                            binding.amount = if amount > 0 { Some(amount) } else { None };
+                        } else {
+                            binding.amount = None;
+                            ui.label("-");
+                        }
+                        ui.add(DragValue::new(&mut binding.debounce_ms).clamp_range(0..=MAX_DEBOUNCE_MS).suffix(" ms"));
+                        // 0 stands for no limit.
+                        let mut rate = binding.max_per_second.unwrap_or(0);
+                        ui.add(DragValue::new(&mut rate)
+                            .clamp_range(0..=MAX_RATE)
+                            .custom_formatter(|n, _| if n == 0.0 { "any".to_owned() } else { format!("{}", n) }));
+                        binding.max_per_second = if rate > 0 { Some(rate) } else { None };
+                        let text = if settings.capturing == Some(i) { "Press a key…".to_owned() } else { binding.key_label() };
+                        if ui.button(text).clicked() {
+                            settings.capturing = Some(i);
+                        }
+                        if ui.button("Remove").clicked() {
+                            remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if let Some(i) = remove {
+                    settings.bindings.remove(i);
+                    settings.capturing = None;
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Add binding").clicked() {
+                        settings.bindings.push(Binding::new("Home", Action::Increment));
+                        settings.capturing = Some(settings.bindings.len() - 1);
+                    }
+                    if ui.button("Restore defaults").clicked() {
+                        settings.bindings = default_bindings();
+                        settings.capturing = None;
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        done = Some(Some(settings.bindings.clone()));
+                    }
+                    if ui.button("Cancel").clicked() {
+                        done = Some(None);
+                    }
+                });
+            });
+        if !open {
+            done = Some(None);
+        }
+        match done {
+            Some(save) => self.close_key_settings(ctx, save),
+            None => self.key_settings = Some(settings),
+        }
+    }
+
+    fn was_submitted(re: &Response) -> bool {
+        re.lost_focus() && re.ctx.input().key_pressed(egui::Key::Enter)
     }
 
     fn get_current(&self) -> Option<&Pokemon> {
-        return self.pokemons.get(self.current_idx);
+        self.pokemons.get(self.current_idx)
     }
 
     fn add_new_pokemon(&mut self) {
-        match add_new_counter(&self.name_field) {
-            Ok(_) => {}
-            Err(_) => {self.error_message = "Failed to add new counter".to_owned();},
+        if let Err(err) = self.store.add_new_counter(&self.name_field) {
+            self.error_message = err.to_string();
+            return;
         }
-        let id = match get_row_id(&self.name_field) {
-            Ok(r) => r,
-            Err(_) => {
-                self.error_message = "Failed to reach DB".to_owned();
-                0
-            }
-        };
-        let res = read_counter(id);
+        let res = self.store.get_row_id(&self.name_field)
+            .and_then(|id| self.store.read_counter(id));
         match res {
             Ok(v) => {
                 self.pokemons.push(v);
                 self.current_idx = self.pokemons.len() - 1;
+                self.error_message.clear();
             }
-            Err(_) => {self.error_message = "Failed to read pokemon".to_owned();},
+            Err(err) => {self.error_message = err.to_string();},
         };
     }
 
     fn counter_button(&mut self, ui: &mut Ui) {
-        let button = ui.add_sized(COUNT_BUTTON_SIZE, Button::new("Count"));
+        let step = self.current_step();
+        let text = if step == 1 { "Count".to_owned() } else { format!("Count +{}", step) };
+        let button = ui.add_sized(COUNT_BUTTON_SIZE, Button::new(text));
         if button.clicked() {
-           self.update_counter(1);
+           self.press(None, Source::Button);
+        };
+    }
+    /// Indices of the counters a press goes to: every linked counter when the
+    /// current one is linked, otherwise just the current one.
+    fn press_targets(&self) -> Vec<usize> {
+        match self.get_current() {
+            Some(current) if current.linked => self.pokemons.iter()
+                .enumerate()
+                .filter(|(_, p)| p.linked)
+                .map(|(i, _)| i)
+                .collect(),
+            Some(_) => vec![self.current_idx],
+            None => vec![],
+        }
+    }
+    /// Counts a press on the current counter and the counters linked to it.
+    /// `amount` replaces each counter's step when given.
+    fn press(&mut self, amount: Option<i32>, source: Source) {
+        let targets = self.press_targets();
+        if targets.is_empty() {
+            self.error_message = DBError::NoActiveCounter.to_string();
+            return;
+        }
+        let changes = targets.iter()
+            .map(|&i| (self.pokemons[i].id, amount.unwrap_or(self.pokemons[i].step)))
+            .collect::<Vec<_>>();
+        match self.store.add_to_counters(&changes, source) {
+            Ok(counts) => {
+                for (&i, count) in targets.iter().zip(counts) {
+                    self.pokemons[i].update_counter(count);
+                }
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    fn set_linked(&mut self, idx: usize, linked: bool) {
+        let id = match self.pokemons.get(idx) {
+            Some(v) => v.id,
+            None => return,
+        };
+        match self.store.set_linked(id, linked) {
+            Ok(_) => {
+                self.pokemons[idx].linked = linked;
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    /// Every active counter with its sprite and odds. Clicking a name selects it.
+    fn counter_grid(&mut self, ui: &mut Ui) {
+        let counters = self.pokemons.iter().map(|p| (p.id, p.name.clone())).collect::<Vec<_>>();
+        for (id, name) in counters {
+            self.ensure_sprite(ui.ctx(), id, &name);
+        }
+        let mut select = None;
+        let mut link = None;
+        ScrollArea::vertical().max_height(GRID_MAX_HEIGHT).show(ui, |ui| {
+            Grid::new("counter-grid").spacing([24.0, 12.0]).show(ui, |ui| {
+                for (i, pkmn) in self.pokemons.iter().enumerate() {
+                    ui.vertical(|ui| {
+                        if let Some(Some(image)) = self.sprites.get(&pkmn.name) {
+                            image.show_max_size(ui, GRID_SPRITE_SIZE);
+                        }
+                        if ui.selectable_label(i == self.current_idx, &pkmn.name).clicked() {
+                            select = Some(i);
+                        }
+                        ui.label(format!("Resets: {}", pkmn.counter));
+                        let odds = self.counter_odds(pkmn);
+                        ui.label(odds.map_or("Odds: -".to_owned(), |o| format!("Odds: 1/{:.0}", 1.0 / o)));
+                        let mut linked = pkmn.linked;
+                        if ui.checkbox(&mut linked, "Linked").changed() {
+                            link = Some((i, linked));
+                        }
+                    });
+                    if (i + 1) % GRID_COLUMNS == 0 { ui.end_row(); }
+                }
+            });
+        });
+        if let Some(i) = select {
+            self.current_idx = i;
+        }
+        if let Some((i, linked)) = link {
+            self.set_linked(i, linked);
+        }
+    }
+    /// The odds per encounter of a counter's saved game and methods.
+    fn counter_odds(&self, pkmn: &Pokemon) -> Option<f64> {
+        self.rules.hunt_probability(pkmn)
+    }
+    /// How much one press adds to the current counter.
+    fn current_step(&self) -> i32 {
+        self.get_current().map_or(1, |p| p.step)
+    }
+    fn update_counter(& mut self, amount: i32, source: Source) {
+        self.change_current(|store, id| store.add_counter(id, amount, source));
+    }
+    fn set_counter(&mut self, value: i32) {
+        self.change_current(|store, id| store.set_counter(id, value, Source::Button));
+    }
+    /// Runs a database change against the current counter and shows the resulting count.
+    fn change_current(&mut self, change: impl FnOnce(&mut CounterStore, i32) -> db_handler::Result<i32>) {
+        self.change_counter(self.current_idx, change);
+    }
+    fn change_counter(&mut self, idx: usize, change: impl FnOnce(&mut CounterStore, i32) -> db_handler::Result<i32>) {
+        let id = match self.pokemons.get(idx) {
+            Some(v) => v.id,
+            None => {
+                self.error_message = DBError::NoActiveCounter.to_string();
+                return;
+            }
+        };
+        match change(&mut self.store, id) {
+            Ok(count) => {
+                if let Some(v) = self.pokemons.get_mut(idx) {
+                    v.update_counter(count);
+                }
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    fn counter_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("-").clicked() {
+                self.press(Some(-1), Source::Button);
+            }
+            ui.add(DragValue::new(&mut self.add_amount).clamp_range(1..=MAX_STEP));
+            if ui.button(format!("+{}", self.add_amount)).clicked() {
+                self.update_counter(self.add_amount, Source::Button);
+            }
+            ui.separator();
+            ui.add(DragValue::new(&mut self.set_value).clamp_range(0..=i32::MAX));
+            if ui.button("Set").clicked() {
+                self.set_counter(self.set_value);
+            }
+            ui.separator();
+            if ui.button("Reset").clicked() && self.get_current().is_some() {
+                self.confirm_reset = true;
+            }
+            ui.separator();
+            if ui.button("Found it!").clicked() {
+                self.finish_hunt(self.current_idx, HuntStatus::Found);
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Break chain").clicked() {
+                self.set_chain(0);
+            }
+            ui.add(DragValue::new(&mut self.chain_value).clamp_range(0..=i32::MAX));
+            if ui.button("Set chain").clicked() {
+                self.set_chain(self.chain_value);
+            }
+            ui.separator();
+            let mut step = self.current_step();
+            ui.label("Step");
+            let re = ui.add_enabled(self.get_current().is_some(), DragValue::new(&mut step).clamp_range(1..=MAX_STEP))
+                .on_hover_text("Encounters counted per press, e.g. 5 for hordes");
+            if re.changed() {
+                self.set_step(step);
+            }
+        });
+    }
+    fn set_chain(&mut self, value: i32) {
+        let id = match self.get_current() {
+            Some(v) => v.id,
+            None => {
+                self.error_message = DBError::NoActiveCounter.to_string();
+                return;
+            }
+        };
+        match self.store.set_chain(id, value) {
+            Ok(chain) => {
+                if let Some(v) = self.pokemons.get_mut(self.current_idx) {
+                    v.chain = chain;
+                }
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    fn set_step(&mut self, step: i32) {
+        let id = match self.get_current() {
+            Some(v) => v.id,
+            None => return,
+        };
+        match self.store.set_step(id, step) {
+            Ok(step) => {
+                if let Some(v) = self.pokemons.get_mut(self.current_idx) {
+                    v.step = step;
+                }
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    fn reset_dialog(&mut self, ctx: &Context) {
+        if !self.confirm_reset { return; }
+        let name = match self.get_current() {
+            Some(v) => v.name.clone(),
+            None => {
+                self.confirm_reset = false;
+                return;
+            }
+        };
+        Window::new("Reset counter?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("Reset {:?} to 0? You can still undo this with Ctrl+Z.", name));
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        self.confirm_reset = false;
+                        self.set_counter(0);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_reset = false;
+                    }
+                });
+            });
+    }
+    /// Undoes (or redoes) the last change to the current counter, together with
+    /// the linked counters it was counted on so a press is taken back everywhere.
+    fn step_history(&mut self, undo: bool) {
+        let id = match self.get_current() {
+            Some(v) => v.id,
+            None => return,
+        };
+        let res = if undo { self.store.undo_batch(id) } else { self.store.redo_batch(id) };
+        match res {
+            Ok(counts) if counts.is_empty() => {
+                self.error_message = format!("Nothing to {}.", if undo { "undo" } else { "redo" });
+            }
+            Ok(counts) => {
+                for (id, count) in counts {
+                    if let Some(v) = self.pokemons.iter_mut().chain(self.archive.iter_mut()).find(|p| p.id == id) {
+                        v.update_counter(count);
+                    }
+                }
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    /// Archives the hunt at `idx` with the odds of its saved game and methods.
+    fn finish_hunt(&mut self, idx: usize, status: HuntStatus) {
+        let id = match self.pokemons.get(idx) {
+            Some(v) => v.id,
+            None => {
+                self.error_message = DBError::NoActiveCounter.to_string();
+                return;
+            }
+        };
+        let odds = self.counter_odds(&self.pokemons[idx]);
+        match self.store.finish_hunt(id, status, odds) {
+            Ok(finished) => {
+                self.pokemons.remove(idx);
+                self.archive.push(finished);
+                if self.current_idx > idx || self.current_idx >= self.pokemons.len() {
+                    self.current_idx = self.current_idx.saturating_sub(1);
+                }
+                self.error_message.clear();
+            }
+            Err(err) => {self.error_message = err.to_string();},
+        }
+    }
+    fn history_window(&mut self, ctx: &Context) {
+        let mut open = self.show_history;
+        let mut reopen = None;
+        Window::new("Hunt history")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                if self.archive.is_empty() {
+                    ui.label("No finished hunts yet.");
+                    return;
+                }
+                ScrollArea::vertical().show(ui, |ui| {
+                    Grid::new("history-grid").striped(true).show(ui, |ui| {
+                        ui.strong("Pokémon");
+                        ui.strong("Status");
+                        ui.strong("Encounters");
+                        ui.strong("Odds");
+                        ui.strong("Date");
+                        ui.end_row();
+                        for (i, pkmn) in self.archive.iter().enumerate() {
+                            ui.label(&pkmn.name);
+                            ui.label(pkmn.status.as_str());
+                            match &pkmn.result {
+                                Some(r) => {
+                                    ui.label(r.final_count.to_string());
+                                    ui.label(r.odds.map_or("-".to_owned(), |o| format!("1/{:.0}", 1.0 / o)));
+                                    ui.label(format_date(r.finished_at));
+                                }
+                                None => {
+                                    ui.label(pkmn.counter.to_string());
+                                    ui.label("-");
+                                    ui.label("-");
+                                }
+                            }
+                            if ui.button("Resume").clicked() {
+                                reopen = Some(i);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        self.show_history = open;
+        if let Some(i) = reopen {
+            let id = self.archive[i].id;
+            match self.store.reopen_hunt(id) {
+                Ok(pkmn) => {
+                    self.archive.remove(i);
+                    self.pokemons.push(pkmn);
+                    self.current_idx = self.pokemons.len() - 1;
+                    self.error_message.clear();
+                }
+                Err(err) => {self.error_message = err.to_string();},
+            }
+        }
+    }
+    fn rename_dialog(&mut self, ctx: &Context) {
+        let (id, mut new_name) = match self.renaming.take() {
+            Some(r) => r,
+            None => return,
         };
+        // looked up by id, the list may have changed since the dialog opened.
+        let (idx, old_name) = match self.pokemons.iter().position(|p| p.id == id) {
+            Some(i) => (i, self.pokemons[i].name.clone()),
+            None => return,
+        };
+        let mut done = false;
+        Window::new("Rename counter")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("New name for {:?}", old_name));
+                let re = ui.text_edit_singleline(&mut new_name);
+                ui.horizontal(|ui| {
+                    if ui.button("Rename").clicked() || PokemonCounter::was_submitted(&re) {
+                        done = true;
+                        match self.store.rename_counter(id, &new_name) {
+                            Ok(_) => {
+                                self.sprites.remove(&old_name);
+                                self.pokemons[idx].name = new_name.clone();
+                                self.error_message.clear();
+                                self.retarget_bindings(ctx, &old_name, Some(&new_name));
+                            }
+                            Err(err) => {self.error_message = err.to_string();},
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        done = true;
+                    }
+                });
+            });
+        if !done {
+            self.renaming = Some((id, new_name));
+        }
     }
-    fn update_counter(& mut self, amount: i32) {
-        let current = self.get_current_mut();
-        match current {
-            Some(v) => {
-                v.update_counter(add_to_counter(v.id, amount));
+    /// Keeps "increment counter" bindings pointing at a renamed counter,
+    /// or drops them when `new_name` is `None` because the counter was deleted.
+    fn retarget_bindings(&mut self, ctx: &Context, old_name: &str, new_name: Option<&str>) {
+        let retarget = |bindings: &mut Vec<Binding>| {
+            match new_name {
+                Some(new_name) => {
+                    for binding in bindings.iter_mut().filter(|b| b.counter.as_deref() == Some(old_name)) {
+                        binding.counter = Some(new_name.to_owned());
+                    }
+                }
+                None => bindings.retain(|b| b.counter.as_deref() != Some(old_name)),
             }
-            None => {}
+        };
+        if let Some(settings) = self.key_settings.as_mut() {
+            retarget(&mut settings.bindings);
+            settings.capturing = None;
+        }
+        let mut bindings = self.bindings.clone();
+        retarget(&mut bindings);
+        if bindings == self.bindings { return; }
+        self.rebind(ctx, bindings);
+        let mut config = load_config();
+        config.bindings = self.bindings.clone();
+        if let Err(err) = save_config(&config) {
+            self.error_message = format!("Could not save key bindings. {}", err);
         }
     }
+    fn delete_dialog(&mut self, ctx: &Context) {
+        let id = match self.confirm_delete {
+            Some(id) => id,
+            None => return,
+        };
+        let (idx, name) = match self.pokemons.iter().position(|p| p.id == id) {
+            Some(i) => (i, self.pokemons[i].name.clone()),
+            None => {
+                self.confirm_delete = None;
+                return;
+            }
+        };
+        Window::new("Delete counter?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("Delete {:?} and its whole history? This can't be undone.", name));
+                ui.horizontal(|ui| {
+                    if ui.button("Delete").clicked() {
+                        self.confirm_delete = None;
+                        match self.store.delete_counter(id) {
+                            Ok(_) => {
+                                self.sprites.remove(&name);
+                                self.pokemons.remove(idx);
+                                if self.current_idx > idx || self.current_idx >= self.pokemons.len() {
+                                    self.current_idx = self.current_idx.saturating_sub(1);
+                                }
+                                self.error_message.clear();
+                                self.retarget_bindings(ctx, &name, None);
+                            }
+                            Err(err) => {self.error_message = err.to_string();},
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_delete = None;
+                    }
+                });
+            });
+    }
     fn add_new_text_field(&mut self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             ui.label("Enter Pokemon Name");
@@ -240,18 +970,19 @@ impl PokemonCounter {
         });
     }
     fn select_generation_dropdown(&mut self, ui: &mut Ui) {
+        let selected = self.current_game().map_or("", |g| g.name.as_str()).to_owned();
         ui.horizontal(|ui| {
             ui.label("Select the generation ");
             egui::ComboBox::from_id_source("generation-selector")
                 .width(128.0)
-                .selected_text(format!("{:?}", self.sel_gen))
+                .selected_text(selected)
                 .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.sel_gen, Generation::Gen2or3, "Generation 2 or 3");
-                    ui.selectable_value(&mut self.sel_gen, Generation::Gen4, "Generation 4");
-                    ui.selectable_value(&mut self.sel_gen, Generation::Gen5, "Generation 5");
-                    ui.selectable_value(&mut self.sel_gen, Generation::Gen6, "Generation 6");
-                    ui.selectable_value(&mut self.sel_gen, Generation::Gen7, "Generation 7");
-                    ui.selectable_value(&mut self.sel_gen, Generation::Gen8, "Generation 8");
+                    for game in &self.rules.games {
+                        let checked = self.settings.game.as_deref() == Some(game.id.as_str());
+                        if ui.selectable_label(checked, &game.name).clicked() && !checked {
+                            self.settings = HuntSettings { game: Some(game.id.clone()), ..Default::default() };
+                        }
+                    }
                 }
             );
         });
@@ -259,269 +990,298 @@ impl PokemonCounter {
     fn odds_calculator(&mut self, ui: &mut Ui) {
         ui.separator();
         if self.get_current().is_none() { return;}
-            let counter = self.get_current().unwrap().counter;
-            match self.sel_gen {
-                Generation::Gen2or3 => {
-                    self.gen2_calculator(ui);
-                },
-                Generation::Gen4 => {
-                    self.gen4_calculator(ui);
-                },
-                Generation::Gen5 => {
-                    self.gen5_calculator(ui);
-                }
-                Generation::Gen6 => {
-                    self.gen6_calculator(ui);
-                }
-                Generation::Gen7 => {
-                    self.gen7_calculator(ui);
-                }
-                Generation::Gen8 => {
-                    self.gen8_calculator(ui);
-                }
-                _ => {ui.label("Generation not implemented!");}
+            let (counter, chain) = self.get_current().map(|v| (v.counter, v.chain.max(0) as u32)).unwrap();
+            let progress = Progress { chain, search_level: self.settings.search_level };
+            self.methods_grid(ui);
+            let mut uses_chain = false;
+            let mut outbreak = None;
+            if let Some(game) = self.current_game() {
+                let (odds, cur_odds) = (game.base_probability(), game.probability(&self.settings.methods, progress));
+                uses_chain = game.uses_chain(&self.settings.methods);
+                outbreak = game.outbreak_spawns(&self.settings.methods)
+                    .zip(game.outbreak_probability(&self.settings.methods, progress));
+                self.odds = odds;
+                self.cur_odds = cur_odds;
+                self.track_odds(counter);
             }
-            
+            let changes = self.odds_history.iter().map(|c| (c.start_count, c.odds)).collect::<Vec<_>>();
+
             ui.separator();
             Frame::none().fill(egui::Color32::from_gray(24)).show(ui, |ui| {
             ui.label(format!("The odds are: {:.3}%", convert_to_percentage(self.odds)));
-            ui.label(format!("Your odds are: {:.3}%", convert_to_percentage(self.cur_odds)));
+            if uses_chain {
+                ui.label(format!("Your odds are: {:.3}% at chain {}", convert_to_percentage(self.cur_odds), chain));
+            } else {
+                ui.label(format!("Your odds are: {:.3}%", convert_to_percentage(self.cur_odds)));
+            }
+            if let Some((spawns, p)) = outbreak {
+                ui.label(format!("Per outbreak of {} Pokémon: {:.3}%", spawns, convert_to_percentage(p)));
+            }
             ui.label(format!("50% chance within: {} tries", num_tries_for_x_percent_chance(0.5, self.cur_odds)));
             ui.label(format!("75% chance within: {} tries", num_tries_for_x_percent_chance(0.75, self.cur_odds)));
             ui.label(format!("99% chance within: {} tries", num_tries_for_x_percent_chance(0.99, self.cur_odds)));
+            ui.label(format!("Expected encounters: {:.0}", expected_encounters(self.cur_odds)));
             ui.label(format!("Current chance: {:.3}%", 
-                convert_to_percentage(probability_within_n_tries(counter, self.cur_odds))
+                convert_to_percentage(probability_with_changing_odds(&changes, counter))
             ));
-            ui.allocate_exact_size(
-                Vec2::new(ui.available_width(), ui.available_height()), 
-                Sense::hover());
+            let luck = convert_to_percentage(luck_percentile(counter, self.cur_odds));
+            if counter > median_encounters(self.cur_odds) {
+                ui.label(format!("You're unluckier than {:.0}% of hunters", luck));
+            } else {
+                ui.label(format!("{:.0}% of hunters would have found it by now", luck));
+            }
+            ui.label(format!("Chance of 2 or more by now: {:.3}%",
+                convert_to_percentage(probability_at_least_k(2, counter, self.cur_odds))
+            ));
+            self.chance_plot(ui, counter, &changes);
             });
     }
-
-    fn gen2_calculator(&mut self, ui: &mut Ui) {
-        self.odds = 1.0/GEN_2_TO_5_ODDS;
-        self.cur_odds = self.odds;
-    }
-    fn gen4_calculator(&mut self, ui: &mut Ui) {
-        let mut clicked = false;
-        self.odds = 1.0/GEN_2_TO_5_ODDS;
-        self.cur_odds = self.calc_gen4_odds();
-        ui.label("Special Methods");
-        Grid::new("gen6-grid").show(ui, |ui| {
-            if ui.checkbox(&mut self.gen4_mod.masuda, "Masuda Method").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen4_mod.radar, "Poké Radar chaining >40").clicked() {clicked = true}
-        });
-        if clicked {self.cur_odds = self.calc_gen4_odds();}
+    /// The chance of having found a shiny over encounters, with the current count and the
+    /// thresholds marked. A second curve follows the odds history once the odds have changed.
+    fn chance_plot(&self, ui: &mut Ui, counter: i32, changes: &[(i32, f64)]) {
+        if self.cur_odds <= 0.0 {
+            ui.allocate_exact_size(Vec2::new(ui.available_width(), ui.available_height()), Sense::hover());
+            return;
+        }
+        let thresholds = CHART_THRESHOLDS.map(|x| (x, encounters_for_chance(x, self.cur_odds)));
+        let max_n = thresholds[thresholds.len() - 1].1.max(counter + counter / 10);
+        let to_points = |curve: Vec<(i32, f64)>| curve.into_iter()
+            .map(|(n, p)| [n as f64, convert_to_percentage(p)])
+            .collect::<Vec<_>>();
+        let current = to_points(chance_curve(&[(0, self.cur_odds)], max_n, CHART_SAMPLES));
+        let history = changes.windows(2).any(|w| w[0].1 != w[1].1)
+            .then(|| to_points(chance_curve(changes, max_n, CHART_SAMPLES)));
+        let now = [counter as f64, convert_to_percentage(probability_with_changing_odds(changes, counter))];
+        Plot::new("chance-plot")
+            .height(ui.available_height().max(MIN_CHART_HEIGHT))
+            .legend(Legend::default())
+            .include_y(0.0)
+            .include_y(100.0)
+            .allow_scroll(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(PlotPoints::from(current)).name("Current odds"));
+                if let Some(history) = history {
+                    plot_ui.line(Line::new(PlotPoints::from(history)).name("Odds history"));
+                }
+                for (x, n) in thresholds {
+                    let point = [n as f64, convert_to_percentage(x)];
+                    plot_ui.points(Points::new(point).radius(4.0).name("Thresholds"));
+                    plot_ui.text(Text::new(PlotPoint::new(point[0], point[1]), format!("{:.0}%", point[1]))
+                        .anchor(Align2::RIGHT_BOTTOM));
+                }
+                plot_ui.vline(VLine::new(now[0]).name("Current count"));
+                plot_ui.points(Points::new(now).radius(5.0).shape(MarkerShape::Diamond).name("Current count"));
+            });
     }
-    fn gen5_calculator(&mut self, ui: &mut Ui) {
-        let mut clicked = false;
-        self.odds = 1.0/GEN_2_TO_5_ODDS;
-        self.cur_odds = self.calc_gen5_odds();
-        ui.label("Special Methods");
-        Grid::new("gen6-grid").show(ui, |ui| {
-            if ui.checkbox(&mut self.gen5_mod.masuda, "Masuda Method").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen5_mod.charm, "Shiny Charm").clicked() {clicked = true}
-        });
-        if clicked {self.cur_odds = self.calc_gen5_odds();}
+    /// Records the odds in the current counter's history when they differ from the last entry.
+    fn track_odds(&mut self, counter: i32) {
+        let id = match self.settings_for {
+            Some(id) if self.cur_odds > 0.0 => id,
+            _ => return,
+        };
+        let unchanged = self.odds_history.iter()
+            .take_while(|c| c.start_count <= counter)
+            .last()
+            .is_some_and(|c| c.odds == self.cur_odds);
+        if unchanged { return; }
+        match self.store.record_odds(id, counter, self.cur_odds) {
+            Ok(_) => {
+                self.odds_history.retain(|c| c.start_count < counter);
+                self.odds_history.push(OddsChange { start_count: counter, odds: self.cur_odds });
+            }
+            Err(err) => self.error_message = err.to_string(),
+        }
     }
-    fn gen6_calculator(&mut self, ui: &mut Ui) {
-        let mut clicked = false;
-        self.odds = 1.0/GEN_6_PLUS_ODDS;
-        self.cur_odds = self.calc_gen6_odds();
+    /// A checkbox for each method of the selected game, two per row.
+    fn methods_grid(&mut self, ui: &mut Ui) {
+        let game = match self.settings.game.as_deref().and_then(|id| self.rules.game(id)) {
+            Some(g) if !g.methods.is_empty() => g,
+            _ => return,
+        };
         ui.label("Special Methods");
-        Grid::new("gen6-grid").show(ui, |ui| {
-            if ui.checkbox(&mut self.gen6_mod.masuda, "Masuda Method").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen6_mod.charm, "Shiny Charm").clicked() {clicked = true}
-            ui.end_row();
-            if ui.checkbox(&mut self.gen6_mod.radar, "Poké Radar chaining >40").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen6_mod.safari, "Friend Safari").clicked() {clicked = true}
-            ui.end_row();
-            if ui.checkbox(&mut self.gen6_mod.fishing, "Consecutive fishing: ≥20").clicked() {clicked = true}
-            //ui.checkbox(&mut self.gen6_mod.hidden, "Hidden Pokémon: Search Level 200 + X")//.clicked() {clicked = true}
+        Grid::new("methods-grid").show(ui, |ui| {
+            for (i, method) in game.single_methods().enumerate() {
+                let mut on = self.settings.methods.contains(&method.id);
+                if ui.checkbox(&mut on, &method.name).clicked() {
+                    game.toggle(&mut self.settings.methods, &method.id, on);
+                }
+                if i % 2 == 1 { ui.end_row(); }
+            }
         });
-        
-        if clicked {self.cur_odds = self.calc_gen6_odds();}
+        for group in game.groups() {
+            let selected = game.group_methods(group)
+                .find(|m| self.settings.methods.contains(&m.id));
+            ui.horizontal(|ui| {
+                ui.label(group);
+                egui::ComboBox::from_id_source(("method-group", group))
+                    .selected_text(selected.map_or("None", |m| m.name.as_str()))
+                    .show_ui(ui, |ui| {
+                        if ui.selectable_label(selected.is_none(), "None").clicked() {
+                            if let Some(m) = selected {
+                                game.toggle(&mut self.settings.methods, &m.id, false);
+                            }
+                        }
+                        for method in game.group_methods(group) {
+                            let checked = selected.map(|m| &m.id) == Some(&method.id);
+                            if ui.selectable_label(checked, &method.name).clicked() {
+                                game.toggle(&mut self.settings.methods, &method.id, true);
+                            }
+                        }
+                    });
+            });
+        }
+        if game.uses_search_level(&self.settings.methods) {
+            ui.horizontal(|ui| {
+                ui.label("Search level");
+                ui.add(DragValue::new(&mut self.settings.search_level).clamp_range(0..=MAX_SEARCH_LEVEL));
+            });
+        }
     }
-    fn gen7_calculator(&mut self, ui: &mut Ui) {
-        let mut clicked = false;
-        self.odds = 1.0/GEN_6_PLUS_ODDS;
-        self.cur_odds = self.calc_gen7_odds();
-        ui.label("Special Methods");
-        Grid::new("gen6-grid").show(ui, |ui| {
-            if ui.checkbox(&mut self.gen7_mod.masuda, "Masuda Method").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen7_mod.charm, "Shiny Charm").clicked() {clicked = true}
-            ui.end_row();
-            if ui.checkbox(&mut self.gen7_mod.sos, "SOS Battles: ≥31").clicked() {clicked = true}
-        });
-        if clicked {self.cur_odds = self.calc_gen7_odds();}
+    fn current_game(&self) -> Option<&GameRules> {
+        self.settings.game.as_deref().and_then(|id| self.rules.game(id))
     }
-    fn gen8_calculator(&mut self, ui: &mut Ui) {
-        let mut clicked = false;
-        self.odds = 1.0/GEN_6_PLUS_ODDS;
-        self.cur_odds = self.calc_gen8_odds();
-        ui.label("Special Methods");
-        Grid::new("gen6-grid").show(ui, |ui| {
-            if ui.checkbox(&mut self.gen8_mod.masuda, "Masuda Method").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen8_mod.charm, "Shiny Charm").clicked() {clicked = true}
-            ui.end_row();
-            if ui.checkbox(&mut self.gen8_mod.radar, "Poké Radar chaining: ≥40").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen8_mod.dynamax, "Dynamax Adventure").clicked() {clicked = true}
-            ui.end_row();
-            if ui.checkbox(&mut self.gen8_mod.mass, "Mass outbreak").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen8_mod.massive, "Massive mass outbreak").clicked() {clicked = true}
-            ui.end_row();
-            if ui.checkbox(&mut self.gen8_mod.dex10, "Pokédex research level 10").clicked() {clicked = true}
-            if ui.checkbox(&mut self.gen8_mod.dexmax, "Pokédex research  level max").clicked() {clicked = true}
-        });
-        if ui.checkbox(&mut self.gen8_mod.underground, "Grand Underground, after 'something good happens'").clicked() {clicked = true}
-        if clicked {self.cur_odds = self.calc_gen8_odds();}
+    /// Selects the game and methods saved with the current counter when it changes.
+    fn load_hunt_settings(&mut self) {
+        let (id, settings) = match self.get_current() {
+            Some(v) => (v.id, v.settings.clone()),
+            None => return,
+        };
+        if self.settings_for == Some(id) { return; }
+        self.settings_for = Some(id);
+        self.settings = settings;
+        self.odds_history = match self.store.get_odds_history(id) {
+            Ok(h) => h,
+            Err(err) => {
+                self.error_message = err.to_string();
+                vec![]
+            }
+        };
+        if self.current_game().is_none() {
+            self.settings = HuntSettings { game: self.rules.games.first().map(|g| g.id.clone()), ..Default::default() };
+        }
+    }
+    /// Writes the selection back to the current counter if it was changed this frame.
+    fn save_hunt_settings(&mut self) {
+        let current = match self.pokemons.get_mut(self.current_idx) {
+            Some(v) if Some(v.id) == self.settings_for => v,
+            _ => return,
+        };
+        if current.settings == self.settings { return; }
+        match self.store.save_hunt_settings(current.id, &self.settings) {
+            Ok(_) => current.settings = self.settings.clone(),
+            Err(err) => self.error_message = err.to_string(),
+        }
     }
 
     fn current_label(&self, ui: &mut Ui) {
         ui.vertical(|ui| {
             let current = self.get_current();
             ui.label("Currently loaded:");
-            match current {
-                Some(v) => {
-                    ui.label(format!("{:?}", v.name));
-                    ui.label(format!("Resets: {:?}", v.counter));
-                }
-                None => {}
-            };
+            if let Some(v) = current {
+                ui.label(format!("{:?}", v.name));
+                ui.label(format!("Resets: {:?}", v.counter));
+                ui.label(format!("Chain: {:?}", v.chain));
+            }
         });
     }
     fn current_sprite(&mut self, ui: &mut Ui) {
-
-        let current_name = match self.get_current() {
-            Some(v) => v.name.clone(),
+        let (id, current_name) = match self.get_current() {
+            Some(v) => (v.id, v.name.clone()),
             None => return,
         };
-        let path = generate_sprite_path(&current_name);
+        self.ensure_sprite(ui.ctx(), id, &current_name);
+        if let Some(Some(image)) = self.sprites.get(&current_name) {
+            image.show(ui);
+        }
+    }
+    /// Reads the sprite from the database, downloading it in the background on first use.
+    /// A cached sprite that cannot be decoded is dropped and downloaded again.
+    /// The unknown sprite is shown meanwhile, and kept if the download fails so it is not retried every frame.
+    fn ensure_sprite(&mut self, ctx: &Context, id: i32, name: &str) {
+        if self.sprites.contains_key(name) {
+            return;
+        }
+        let cached = self.store.get_sprite(name).ok()
+            .and_then(|r| RetainedImage::from_image_bytes(name, &r.img).ok());
+        if let Some(image) = cached {
+            self.sprites.insert(name.to_owned(), Some(image));
+            return;
+        }
+        if let Err(err) = self.store.delete_sprite(name) {
+            self.error_message = err.to_string();
+        }
+        let (tx, ctx, fetch_name) = (self.sprite_sender.clone(), ctx.clone(), name.to_owned());
+        thread::spawn(move || {
+            let res = fetch_sprite(&fetch_name);
+            if tx.send((id, fetch_name, res)).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        let placeholder = self.unknown_sprite();
+        self.sprites.insert(name.to_owned(), placeholder);
+    }
 
-        let img_bytes = match get_sprite(&current_name) {
-            Ok(r) => r,
-            Err(_) => {
-                let img_bytes = match reqwest::blocking::get(path.to_ascii_lowercase()) {
-                    Ok(r) => match r.bytes() {
-                        Ok(r) => r,
-                        Err(e) => {
-                            self.error_message = format!("{:?}", e.to_string());
-                            return;
-                        }
-                    },
-                    Err(e) => {
-                        self.error_message = format!("{:?}", e.to_string());
-                        return;
-                    }
-                };
-                match save_sprite(&current_name, img_bytes) {
-                    Ok(b) => b,
-                    Err(_) => return,
-                }
+    /// Caches and shows the sprites downloaded since the last frame. Downloads for counters
+    /// that were renamed or deleted meanwhile are dropped, a renamed counter fetches its new name itself.
+    fn receive_sprites(&mut self) {
+        while let Ok((id, name, res)) = self.sprite_receiver.try_recv() {
+            if !self.pokemons.iter().chain(&self.archive).any(|p| p.id == id && p.name == name) {
+                continue;
             }
-        };
-        let image = match RetainedImage::from_image_bytes(current_name, &img_bytes) {
-            Ok(r) => r,
-            Err(_) => {
-                match RetainedImage::from_image_bytes(
-                    "unknown",
-                    include_bytes!("../img/unknown.png"),
-                ) {
-                    Ok(r) => r,
-                    Err(_) => {
-                        self.error_message = "Error loading sprite".to_owned();
-                        return;
+            match res {
+                Ok(sprite) => match RetainedImage::from_image_bytes(&name, &sprite.img) {
+                    Ok(image) => {
+                        if let Err(err) = self.store.save_sprite(&name, &sprite) {
+                            // still show the sprite, it just won't be cached.
+                            self.error_message = err.to_string();
+                        }
+                        self.sprites.insert(name, Some(image));
                     }
-                }
+                    Err(_) => self.error_message = format!("Could not read the sprite for {:?}", name),
+                },
+                Err(e) => self.error_message = e,
             }
-        };
-        image.show(ui);
-    }
-
-    fn get_gen4_modifier(&self) -> i32 {
-        let mut total_mod = 1;
-        if self.gen4_mod.masuda {total_mod += 4}
-        if self.gen4_mod.radar {total_mod += 40}
-        return total_mod;
-    }
-    fn calc_gen4_odds(&self) -> f64 {
-        self.get_gen4_modifier() as f64 / GEN_2_TO_5_ODDS
-    }
-
-    fn get_gen5_modifier(&self) -> i32 {
-        let mut total_mod = 1;
-        if self.gen5_mod.masuda {total_mod += 5}
-        if self.gen5_mod.charm {total_mod += 2}
-        return total_mod;
-    }
-    fn calc_gen5_odds(&self) -> f64 {
-        self.get_gen5_modifier() as f64 / GEN_2_TO_5_ODDS
-    }
-
-    fn get_gen6_modifier(&self) -> i32 {
-        let mut total_mod = 1;
-        if self.gen6_mod.masuda {total_mod += 5}
-        if self.gen6_mod.radar {total_mod += 81}
-        if self.gen6_mod.charm {total_mod += 2}
-        if self.gen6_mod.safari {total_mod += 4}
-        if self.gen6_mod.fishing {total_mod += 40}
-        //TODO impl hidden pokemon calculation
-        return total_mod;
-    }
-    fn calc_gen6_odds(&self) -> f64 {
-        self.get_gen6_modifier() as f64 / GEN_6_PLUS_ODDS
-    }
-
-    fn get_gen7_modifier(&self) -> i32 {
-        let mut total_mod = 1;
-        if self.gen7_mod.masuda {total_mod += 5}
-        if self.gen7_mod.charm {total_mod += 2}
-        if self.gen7_mod.sos {total_mod += 12}
-        return total_mod;
-    }
-    fn calc_gen7_odds(&self) -> f64 {
-        self.get_gen7_modifier() as f64 / GEN_6_PLUS_ODDS
+        }
     }
 
-    fn get_gen8_modifier(&self) -> i32 {
-        let mut total_mod = 1;
-        if self.gen8_mod.masuda {total_mod += 5}
-        if self.gen8_mod.charm {total_mod += 2}
-        if self.gen8_mod.radar {total_mod += 40}
-        if self.gen8_mod.dynamax {total_mod += 13}
-        if self.gen8_mod.underground {total_mod += 1}
-        if self.gen8_mod.mass {total_mod += 25}
-        if self.gen8_mod.massive {total_mod += 12}
-        if self.gen8_mod.dex10 {total_mod += 1}
-        if self.gen8_mod.dexmax {total_mod += 2}
-        return total_mod;
-    }
-    fn calc_gen8_odds(&self) -> f64 {
-        self.get_gen8_modifier() as f64 / GEN_6_PLUS_ODDS
+    fn unknown_sprite(&mut self) -> Option<RetainedImage> {
+        match RetainedImage::from_image_bytes(
+            "unknown",
+            include_bytes!("../img/unknown.png"),
+        ) {
+            Ok(r) => Some(r),
+            Err(_) => {
+                self.error_message = "Error loading sprite".to_owned();
+                None
+            }
+        }
     }
 }
 
-fn add_to_counter(current_id: i32, amnt: i32) -> i32 {
-    if current_id == -1 {
-        println!("No counter loaded");
-        return -1;
-    }
-    let res = add_counter(current_id, amnt);
-    match res {
-        Ok(count) => {
-            println!("{:?}", count);
-            return count;
-        }
-        Err(_) => {
-            println!("Error, could not increment.");
-            return -1;
-        }
-    }
+fn fetch_sprite(name: &str) -> Result<Sprite, String> {
+    let path = generate_sprite_path(name).to_ascii_lowercase();
+    let response = match reqwest::blocking::get(&path).and_then(|r| r.error_for_status()) {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{:?}", e.to_string())),
+    };
+    let header = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+    let img = response.bytes().map_err(|e| format!("{:?}", e.to_string()))?;
+    let content_type = image_content_type(header.as_deref(), &img)
+        .ok_or_else(|| format!("No sprite found for {:?}", name))?;
+    Ok(Sprite {
+        content_type: Some(content_type),
+        img,
+        source_url: Some(path),
+        fetched_at: Some(now_millis()),
+    })
 }
 
 fn generate_sprite_path(loaded_pkmn: &str) -> String {
     let mut path: String = "https://play.pokemonshowdown.com/sprites/dex/".to_owned();
-    path.push_str(&loaded_pkmn);
+    path.push_str(loaded_pkmn);
     path.push_str(".png");
-    return path;
+    path
 }
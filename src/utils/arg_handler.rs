@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+/// Keep track of your shiny hunts, from a window or the command-line.
+#[derive(Parser, Debug)]
+#[command(name = "counter_app", version, about)]
+pub struct Args {
+    /// Path to the counter database. Overrides the COUNTER_APP_DB
+    /// environment variable and the config file.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub db: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Opens the counter window. This is the default.
+    Gui,
+    /// Starts the interactive command-line counter.
+    Repl,
+    /// Runs a single counter command and exits.
+    #[command(subcommand)]
+    Counter(CounterCommand),
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum CounterCommand {
+    /// Increments the given counter and the counters linked to it by their steps and prints the new count.
+    Inc { name: String },
+    /// Lists the active counters.
+    List {
+        /// Also lists found and abandoned hunts.
+        #[arg(long, short)]
+        all: bool,
+    },
+    /// Prints the current count of the given counter.
+    Show { name: String },
+    /// Decrements the given counter by one and prints the new count.
+    Dec { name: String },
+    /// Adds an amount (which may be negative) to the given counter and prints the new count.
+    Add {
+        name: String,
+        #[arg(allow_hyphen_values = true)]
+        amount: i32,
+    },
+    /// Sets the given counter to an exact count.
+    Set { name: String, count: i32 },
+    /// Sets the given counter back to 0.
+    Reset {
+        name: String,
+        /// Skips the confirmation prompt.
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Renames a counter.
+    Rename { name: String, new_name: String },
+    /// Deletes a counter together with its history.
+    Delete {
+        name: String,
+        /// Skips the confirmation prompt.
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Reverts the last change to the given counter and its linked counters and prints the new count.
+    Undo { name: String },
+    /// Re-applies the last undone change to the given counter and prints the new count.
+    Redo { name: String },
+    /// Prints the encounter log of the given counter as
+    /// tab-separated timestamp (ms since epoch), change and source.
+    History { name: String },
+    /// Prints the chain length of the given counter, or sets it when a value is given.
+    Chain { name: String, value: Option<i32> },
+    /// Prints how much one increment of the given counter adds, or sets it when a value is given.
+    Step { name: String, value: Option<i32> },
+    /// Marks the hunt as found, recording its final count.
+    Found { name: String },
+    /// Marks the hunt as abandoned, recording its final count.
+    Abandon { name: String },
+    /// Recalculates the count of the given counter from its encounter log.
+    Recount { name: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::{Args, Command, CounterCommand};
+
+    #[test]
+    fn test_no_subcommand_defaults_to_none() {
+        let args = Args::try_parse_from(["counter_app"]).unwrap();
+        assert_eq!(None, args.command);
+        assert_eq!(None, args.db);
+    }
+
+    #[test]
+    fn test_counter_inc() {
+        let args = Args::try_parse_from(["counter_app", "counter", "inc", "pikachu"]).unwrap();
+        assert_eq!(Some(Command::Counter(CounterCommand::Inc { name: "pikachu".to_owned() })), args.command);
+    }
+
+    #[test]
+    fn test_global_db_after_subcommand() {
+        let args = Args::try_parse_from(["counter_app", "counter", "list", "--db", "hunts.db"]).unwrap();
+        assert_eq!(Some(Command::Counter(CounterCommand::List { all: false })), args.command);
+        assert_eq!(Some("hunts.db".into()), args.db);
+    }
+
+    #[test]
+    fn test_add_negative_amount() {
+        let args = Args::try_parse_from(["counter_app", "counter", "add", "pikachu", "-3"]).unwrap();
+        assert_eq!(Some(Command::Counter(CounterCommand::Add { name: "pikachu".to_owned(), amount: -3 })), args.command);
+    }
+
+    #[test]
+    fn test_chain_value_is_optional() {
+        let args = Args::try_parse_from(["counter_app", "counter", "chain", "pikachu"]).unwrap();
+        assert_eq!(Some(Command::Counter(CounterCommand::Chain { name: "pikachu".to_owned(), value: None })), args.command);
+        let args = Args::try_parse_from(["counter_app", "counter", "chain", "pikachu", "40"]).unwrap();
+        assert_eq!(Some(Command::Counter(CounterCommand::Chain { name: "pikachu".to_owned(), value: Some(40) })), args.command);
+    }
+
+    #[test]
+    fn test_step_value_is_optional() {
+        let args = Args::try_parse_from(["counter_app", "counter", "step", "seviper", "5"]).unwrap();
+        assert_eq!(Some(Command::Counter(CounterCommand::Step { name: "seviper".to_owned(), value: Some(5) })), args.command);
+    }
+
+    #[test]
+    fn test_show_requires_name() {
+        assert!(Args::try_parse_from(["counter_app", "counter", "show"]).is_err());
+    }
+}
@@ -1,8 +1,13 @@
-use crate::utils::db_handler::{
-    add_new_counter, get_all_counters, get_row_id, increment_counter, read_counter,
-};
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+use std::sync::Mutex;
 
-#[derive(PartialEq, Eq)]
+use crate::utils::arg_handler::CounterCommand;
+use crate::utils::db_handler::{format_date, CounterStore, HuntStatus, Pokemon, Result, Source};
+use crate::utils::hotkey_handler::{Action, Binding};
+use crate::utils::odds_handler::load_rules;
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum State {
     Exit,
     Cont,
@@ -10,21 +15,29 @@ pub enum State {
 }
 
 macro_rules! run_cli {
-    ($id: expr) => {{
+    ($store: expr, $id: expr) => {{
         let mut state = State::Cont;
         while state == State::Cont {
-            let mut line = String::new();
-            std::io::stdin().read_line(&mut line).unwrap();
-            state = handle_input(&line, $id);
+            state = read_input(&mut std::io::stdin().lock(), &$store, $id);
         }
         return state;
     }};
 }
 pub(crate) use run_cli;
 
-pub fn handle_input(line: &str, current_id: i32) -> State {
-    let x = line.split("\r\n").collect::<Vec<&str>>();
-    let commands = x[0].split(' ').collect::<Vec<&str>>();
+/// Reads one line and runs it. The store is only locked once the line is in, so hotkeys
+/// keep working while waiting. End of input or a failed read exits instead of counting as Enter.
+pub fn read_input(input: &mut impl BufRead, store: &Mutex<CounterStore>, current_id: i32) -> State {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => State::Exit,
+        Ok(_) => handle_input(&mut store.lock().unwrap(), &line, current_id),
+    }
+}
+
+pub fn handle_input(store: &mut CounterStore, line: &str, current_id: i32) -> State {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let commands = line.split(' ').collect::<Vec<&str>>();
     let cmd = &commands[0].to_lowercase() as &str;
     match cmd {
         "help" => {
@@ -32,20 +45,97 @@ pub fn handle_input(line: &str, current_id: i32) -> State {
             State::Cont
         }
         "list" => {
-            list_counters();
+            list_counters(store);
+            State::Cont
+        }
+        "archive" => {
+            list_archive(store);
+            State::Cont
+        }
+        "chain" => {
+            match parse_amount(&commands) {
+                Some(value) => print_count_result(store.set_chain(current_id, value), "set chain"),
+                None => print_count_result(store.read_counter(current_id).map(|p| p.chain), "read chain"),
+            }
             State::Cont
         }
+        "step" => {
+            match parse_amount(&commands) {
+                Some(value) => print_count_result(store.set_step(current_id, value), "set step"),
+                None => print_count_result(store.read_step(current_id), "read step"),
+            }
+            State::Cont
+        }
+        "link" | "unlink" => {
+            match store.set_linked(current_id, cmd == "link") {
+                Ok(_) => println!("{}.", if cmd == "link" { "Linked" } else { "Unlinked" }),
+                Err(err) => println!("Error, could not {}. {}", cmd, err),
+            }
+            State::Cont
+        }
+        "break" => {
+            print_count_result(store.set_chain(current_id, 0), "break chain");
+            State::Cont
+        }
+        "found" => finish_hunt(store, current_id, HuntStatus::Found),
+        "abandon" => finish_hunt(store, current_id, HuntStatus::Abandoned),
         "add" => {
-            let commands = add_counter(commands);
-            let next = load_counter(commands);
+            if let Some(amount) = parse_amount(&commands) {
+                print_count_result(store.add_counter(current_id, amount, Source::Cli), "add");
+                return State::Cont;
+            }
+            let commands = add_counter(store, commands);
+            let next = load_counter(store, commands);
             State::Load(next)
         }
+        "new" => {
+            let commands = add_counter(store, commands);
+            let next = load_counter(store, commands);
+            State::Load(next)
+        }
+        "dec" => {
+            print_press_result(press(store, current_id, Some(-1), Source::Cli), "decrement");
+            State::Cont
+        }
+        "set" => {
+            match parse_amount(&commands) {
+                Some(value) => print_count_result(store.set_counter(current_id, value, Source::Cli), "set"),
+                None => println!("Error, no count provided"),
+            }
+            State::Cont
+        }
+        "reset" => {
+            if current_id == -1 {
+                println!("No counter loaded.");
+            } else if confirm("Reset the loaded counter to 0?") {
+                print_count_result(store.reset_counter(current_id, Source::Cli), "reset");
+            }
+            State::Cont
+        }
         "load" => {
-            let next = load_counter(commands);
+            let next = load_counter(store, commands);
             State::Load(next)
         }
         "" => {
-            increment(current_id);
+            increment(store, current_id, Source::Cli);
+            State::Cont
+        }
+        "rename" => {
+            rename_counter(store, &commands);
+            State::Cont
+        }
+        "delete" => {
+            match delete_counter(store, &commands) {
+                Some(id) if id == current_id => State::Load(-1),
+                _ => State::Cont,
+            }
+        }
+        "undo" => {
+            print_undo_result(store.undo_batch(current_id), current_id, "undo");
+            State::Cont
+        }
+        "redo" => {
+            print_undo_result(store.redo_batch(current_id), current_id, "redo");
             State::Cont
         }
         "exit" => State::Exit,
@@ -58,39 +148,101 @@ pub fn handle_input(line: &str, current_id: i32) -> State {
 
 fn print_help() {
     println!();
-    println!("LIST\t\tLists all the existing counters.");
-    println!("ADD name\tAdds a new counter with the given name.");
+    println!("LIST\t\tLists all the active counters.");
+    println!("ARCHIVE\t\tLists found and abandoned hunts.");
+    println!("NEW name\tAdds a new counter with the given name. ADD name does the same.");
+    println!("ADD n\t\tAdds n to the loaded counter.");
+    println!("DEC\t\tSubtracts one from the loaded counter and its linked counters.");
+    println!("SET n\t\tSets the loaded counter to n.");
+    println!("RESET\t\tSets the loaded counter back to 0.");
     println!("LOAD name\tSets the given counter as the active one.");
+    println!("RENAME old new\tRenames a counter.");
+    println!("DELETE name\tDeletes a counter and its history.");
+    println!("CHAIN [n]\tShows the chain length of the loaded counter, or sets it to n.");
+    println!("BREAK\t\tSets the chain of the loaded counter back to 0.");
+    println!("LINK\t\tLinks the loaded counter, so presses on any linked counter count on all of them.");
+    println!("UNLINK\t\tRemoves the loaded counter from the linked counters.");
+    println!("STEP [n]\tShows how much one increment adds to the loaded counter, or sets it to n.");
+    println!("FOUND\t\tMarks the loaded hunt as found and archives it.");
+    println!("ABANDON\t\tMarks the loaded hunt as abandoned and archives it.");
+    println!("UNDO\t\tReverts the last change to the loaded counter and its linked counters.");
+    println!("REDO\t\tRe-applies the last undone change.");
     println!("EXIT\t\tExits application.");
-    println!("Press enter to increment loaded counter.");
-    println!("Press Scroll Lock to increment loaded counter when command-line is not in focus.")
+    println!("Press enter to increment loaded counter by its step.");
+    println!("Press Scroll Lock to increment loaded counter when command-line is not in focus.");
+    println!("Hotkeys of the command-line can be changed under repl_bindings in config.toml.")
 }
 
-fn list_counters() {
-    let result = get_all_counters();
+fn list_counters(store: &CounterStore) {
+    let result = store.get_all_counters();
     let counters = match result {
         Ok(c) => c,
-        Err(_) => {
-            println!("Error, could not fetch counters.");
+        Err(err) => {
+            println!("Error, could not fetch counters. {}", err);
             return;
         }
     };
+    let counters = counters.into_iter().filter(|c| c.status == HuntStatus::Active).collect::<Vec<_>>();
     if !counters.is_empty(){
         for counter in counters {
-            println!("{:?}: {:?}", counter.name, counter.counter);
+            let linked = if counter.linked { " (linked)" } else { "" };
+            println!("{:?}: {:?}{}", counter.name, counter.counter, linked);
         }
     } else {
         println!("No counters saved. Use ADD command to create new.");
     }
 }
 
-fn add_counter(commands: Vec<&str>) -> Vec<&str> {
+fn list_archive(store: &CounterStore) {
+    let counters = match store.get_all_counters() {
+        Ok(c) => c,
+        Err(err) => {
+            println!("Error, could not fetch counters. {}", err);
+            return;
+        }
+    };
+    let mut empty = true;
+    for counter in counters.iter().filter(|c| c.status != HuntStatus::Active) {
+        empty = false;
+        println!("{}", describe_hunt(counter));
+    }
+    if empty {
+        println!("No finished hunts.");
+    }
+}
+
+fn describe_hunt(counter: &Pokemon) -> String {
+    match &counter.result {
+        Some(r) => format!("{}: {} ({} on {})", counter.name, r.final_count, counter.status.as_str(), format_date(r.finished_at)),
+        None => format!("{}: {} ({})", counter.name, counter.counter, counter.status.as_str()),
+    }
+}
+
+fn finish_hunt(store: &mut CounterStore, current_id: i32, status: HuntStatus) -> State {
+    match store.finish_hunt(current_id, status, final_odds(store, current_id)) {
+        Ok(pkmn) => {
+            println!("{}", describe_hunt(&pkmn));
+            State::Load(-1)
+        }
+        Err(err) => {
+            println!("Error, could not finish hunt. {}", err);
+            State::Cont
+        }
+    }
+}
+
+/// The odds of the hunt's saved game and methods, recorded when it is archived.
+fn final_odds(store: &CounterStore, id: i32) -> Option<f64> {
+    store.read_counter(id).ok().and_then(|pkmn| load_rules().hunt_probability(&pkmn))
+}
+
+fn add_counter<'a>(store: &CounterStore, commands: Vec<&'a str>) -> Vec<&'a str> {
     if commands.len() > 1 {
         let param = commands[1];
-        let res = add_new_counter(param);
+        let res = store.add_new_counter(param);
         match res {
             Ok(_) => println!("Added {:?} successfully.", param),
-            Err(_) => println!("Counter with name {:?} already exists.", param),
+            Err(err) => println!("{}", err),
         };
     } else {
         println!("Error, no name provided");
@@ -98,48 +250,308 @@ fn add_counter(commands: Vec<&str>) -> Vec<&str> {
     commands
 }
 
-fn load_counter(commands: Vec<&str>) -> i32 {
+fn load_counter(store: &CounterStore, commands: Vec<&str>) -> i32 {
     if commands.len() > 1 {
         let param = commands[1];
-        let result = get_row_id(param);
+        let result = store.get_row_id(param);
         let id = match result {
             Ok(i) => i,
-            Err(_) => {
-                println!("Counter with name {:?} does not exist.", param);
+            Err(err) => {
+                println!("{}", err);
                 return -1;
             }
         };
-        let res = read_counter(id);
+        let res = store.read_counter(id);
         match res {
             Ok(r) => {
                 println!("Loaded counter {:?}, current count: {:?}", param, r.counter);
-                return id;
+                id
             }
-            Err(_) => {
-                println!("Counter with name {:?} does not exist.", param);
-                return -1;
+            Err(err) => {
+                println!("{}", err);
+                -1
             }
-        };
+        }
     } else {
         println!("Error, no name provided");
-        return -1;
+        -1
+    }
+}
+
+fn rename_counter(store: &mut CounterStore, commands: &[&str]) {
+    if commands.len() < 3 {
+        println!("Error, usage: RENAME old new");
+        return;
+    }
+    let res = store.get_row_id(commands[1])
+        .and_then(|id| store.rename_counter(id, commands[2]));
+    match res {
+        Ok(_) => println!("Renamed {:?} to {:?}.", commands[1], commands[2]),
+        Err(err) => println!("{}", err),
+    }
+}
+
+/// Returns the id of the deleted counter.
+fn delete_counter(store: &mut CounterStore, commands: &[&str]) -> Option<i32> {
+    let name = match commands.get(1) {
+        Some(n) => *n,
+        None => {
+            println!("Error, no name provided");
+            return None;
+        }
+    };
+    let id = match store.get_row_id(name) {
+        Ok(id) => id,
+        Err(err) => {
+            println!("{}", err);
+            return None;
+        }
+    };
+    if !confirm(&format!("Delete {:?} and its history?", name)) {
+        return None;
+    }
+    match store.delete_counter(id) {
+        Ok(_) => {
+            println!("Deleted {:?}.", name);
+            Some(id)
+        }
+        Err(err) => {
+            println!("{}", err);
+            None
+        }
+    }
+}
+
+fn parse_amount(commands: &[&str]) -> Option<i32> {
+    commands.get(1).and_then(|c| c.parse().ok())
+}
+
+/// Asks a yes/no question on stdin, defaulting to no.
+fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N] ", prompt);
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).is_err() {
+        return false;
+    }
+    matches!(line.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn print_count_result(res: Result<i32>, action: &str) {
+    match res {
+        Ok(count) => println!("{:?}", count),
+        Err(err) => println!("Error, could not {}. {}", action, err),
     }
 }
 
-pub fn increment(current_id: i32) -> i32 {
-    if current_id == -1 {
-        println!("No counter loaded");
-        return -1;
+/// Prints the loaded counter's count after an undo or redo, which also steps
+/// the linked counters that were counted in the same press.
+fn print_undo_result(res: Result<Vec<(i32, i32)>>, current_id: i32, action: &str) {
+    match res {
+        Ok(counts) if counts.is_empty() => println!("Nothing to {}.", action),
+        Ok(counts) => {
+            if let Some((_, count)) = counts.iter().find(|(id, _)| *id == current_id) {
+                println!("{:?}", count);
+            }
+            if counts.len() > 1 {
+                println!("Also changed {} linked counter(s).", counts.len() - 1);
+            }
+        }
+        Err(err) => println!("Error, could not {}. {}", action, err),
     }
-    let res = increment_counter(current_id);
+}
+
+/// Counts one press on the loaded counter and on the counters linked to it.
+pub fn increment(store: &mut CounterStore, current_id: i32, source: Source) -> i32 {
+    print_press_result(press(store, current_id, None, source), "increment")
+}
+
+/// Adds `amount`, or each counter's step, to the counter and its linked counters.
+fn press(store: &mut CounterStore, current_id: i32, amount: Option<i32>, source: Source) -> Result<Vec<i32>> {
+    let changes = store.linked_group(current_id)?.into_iter()
+        .map(|id| Ok((id, match amount {
+            Some(amount) => amount,
+            None => store.read_step(id)?,
+        })))
+        .collect::<Result<Vec<_>>>()?;
+    store.add_to_counters(&changes, source)
+}
+
+
+fn print_press_result(res: Result<Vec<i32>>, action: &str) -> i32 {
     match res {
-        Ok(count) => {
-            println!("{:?}", count);
-            return count;
+        Ok(counts) => {
+            println!("{:?}", counts[0]);
+            if counts.len() > 1 {
+                println!("Also counted {} linked counter(s).", counts.len() - 1);
+            }
+            counts[0]
+        }
+        Err(err) => {
+            println!("Error, could not {}. {}", action, err);
+            -1
+        }
+    }
+}
+
+/// Runs a global hotkey against the loaded counter.
+pub fn run_hotkey(store: &mut CounterStore, current_id: i32, binding: &Binding) {
+    match binding.action {
+        Action::Increment => {
+            print_press_result(press(store, current_id, binding.amount, Source::Hotkey), "increment");
+        }
+        Action::Decrement => {
+            let amount = binding.amount.unwrap_or(1);
+            print_press_result(press(store, current_id, Some(-amount), Source::Hotkey), "decrement");
         }
-        Err(_) => {
-            println!("Error, could not increment.");
-            return -1;
+        Action::Undo => print_undo_result(store.undo_batch(current_id), current_id, "undo"),
+        Action::IncrementCounter => {
+            let name = binding.counter.as_deref().unwrap_or_default();
+            match (store.get_row_id(name), binding.amount) {
+                (Ok(id), Some(amount)) => print_count_result(store.add_counter(id, amount, Source::Hotkey), "increment"),
+                (Ok(id), None) => print_count_result(store.increment_counter(id, Source::Hotkey), "increment"),
+                (Err(err), _) => println!("Error, could not find counter {:?}. {}", name, err),
+            }
         }
+        Action::NextCounter | Action::PreviousCounter => println!("Use LOAD to switch counters."),
+    }
+}
+
+/// Runs a one-shot command from the command-line arguments.
+/// Output is kept plain so it can be consumed by scripts.
+pub fn run_command(store: &mut CounterStore, command: &CounterCommand) -> ExitCode {
+    match try_command(store, command) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn try_command(store: &mut CounterStore, command: &CounterCommand) -> Result<()> {
+    match command {
+        CounterCommand::Inc { name } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", press(store, id, None, Source::Cli)?[0]);
+        }
+        CounterCommand::List { all } => {
+            for counter in store.get_all_counters()? {
+                if counter.status == HuntStatus::Active {
+                    println!("{}: {}", counter.name, counter.counter);
+                } else if *all {
+                    println!("{}", describe_hunt(&counter));
+                }
+            }
+        }
+        CounterCommand::Chain { name, value } => {
+            let id = store.get_row_id(name)?;
+            match value {
+                Some(value) => println!("{}", store.set_chain(id, *value)?),
+                None => println!("{}", store.read_counter(id)?.chain),
+            }
+        }
+        CounterCommand::Step { name, value } => {
+            let id = store.get_row_id(name)?;
+            match value {
+                Some(value) => println!("{}", store.set_step(id, *value)?),
+                None => println!("{}", store.read_step(id)?),
+            }
+        }
+        CounterCommand::Found { name } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", describe_hunt(&store.finish_hunt(id, HuntStatus::Found, final_odds(store, id))?));
+        }
+        CounterCommand::Abandon { name } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", describe_hunt(&store.finish_hunt(id, HuntStatus::Abandoned, final_odds(store, id))?));
+        }
+        CounterCommand::Show { name } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", store.read_counter(id)?.counter);
+        }
+        CounterCommand::History { name } => {
+            let id = store.get_row_id(name)?;
+            for encounter in store.get_encounters(id)? {
+                let undone = if encounter.undone { "\tundone" } else { "" };
+                println!("{}\t{:+}\t{}{}", encounter.timestamp, encounter.delta, encounter.source.as_str(), undone);
+            }
+        }
+        CounterCommand::Dec { name } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", store.add_counter(id, -1, Source::Cli)?);
+        }
+        CounterCommand::Add { name, amount } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", store.add_counter(id, *amount, Source::Cli)?);
+        }
+        CounterCommand::Set { name, count } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", store.set_counter(id, *count, Source::Cli)?);
+        }
+        CounterCommand::Reset { name, yes } => {
+            let id = store.get_row_id(name)?;
+            if *yes || confirm(&format!("Reset {:?} to 0?", name)) {
+                println!("{}", store.reset_counter(id, Source::Cli)?);
+            }
+        }
+        CounterCommand::Rename { name, new_name } => {
+            let id = store.get_row_id(name)?;
+            store.rename_counter(id, new_name)?;
+        }
+        CounterCommand::Delete { name, yes } => {
+            let id = store.get_row_id(name)?;
+            if *yes || confirm(&format!("Delete {:?} and its history?", name)) {
+                store.delete_counter(id)?;
+            }
+        }
+        CounterCommand::Undo { name } => {
+            let id = store.get_row_id(name)?;
+            match store.undo(id)? {
+                Some(count) => println!("{}", count),
+                None => eprintln!("Nothing to undo."),
+            }
+        }
+        CounterCommand::Redo { name } => {
+            let id = store.get_row_id(name)?;
+            match store.redo(id)? {
+                Some(count) => println!("{}", count),
+                None => eprintln!("Nothing to redo."),
+            }
+        }
+        CounterCommand::Recount { name } => {
+            let id = store.get_row_id(name)?;
+            println!("{}", store.recompute_counter(id)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::{read_input, State};
+    use crate::utils::db_handler::CounterStore;
+
+    fn store_with_counter() -> (Mutex<CounterStore>, i32) {
+        let store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        (Mutex::new(store), id)
+    }
+
+    #[test]
+    fn test_end_of_input_exits_without_counting() {
+        let (store, id) = store_with_counter();
+        assert_eq!(State::Exit, read_input(&mut "".as_bytes(), &store, id));
+        assert_eq!(0, store.lock().unwrap().read_counter(id).unwrap().counter);
+    }
+
+    #[test]
+    fn test_enter_increments() {
+        let (store, id) = store_with_counter();
+        assert_eq!(State::Cont, read_input(&mut "\n".as_bytes(), &store, id));
+        assert_eq!(1, store.lock().unwrap().read_counter(id).unwrap().counter);
     }
 }
@@ -0,0 +1,212 @@
+use std::{ffi::OsString, fs, io, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::hotkey_handler::{default_bindings, default_repl_bindings, Binding};
+
+pub const DB_ENV_VAR: &str = "COUNTER_APP_DB";
+const APP_DIR: &str = "counter_app";
+const CONFIG_FILE: &str = "config.toml";
+const DB_FILE: &str = "count.db";
+const ODDS_FILE: &str = "odds.toml";
+
+/// Settings read from `config.toml` in the platform config directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub db_path: Option<PathBuf>,
+    pub bindings: Vec<Binding>,
+    /// Global hotkeys of the command-line, kept apart so it does not grab the window's keys.
+    pub repl_bindings: Vec<Binding>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { db_path: None, bindings: default_bindings(), repl_bindings: default_repl_bindings() }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(CONFIG_FILE))
+}
+
+/// Optional overrides for the built-in shiny odds rules.
+pub fn odds_rules_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(ODDS_FILE))
+}
+
+/// Loads the config file, falling back to the defaults when it is missing or unreadable.
+pub fn load_config() -> Config {
+    let path = match config_path() {
+        Some(p) => p,
+        None => return Config::default(),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(_) => return Config::default(),
+    };
+    match toml::from_str(&text) {
+        Ok(c) => c,
+        Err(err) => {
+            println!("Ignoring invalid config file {:?}: {}", path, err);
+            Config::default()
+        }
+    }
+}
+
+/// Writes the config file, creating its directory if needed.
+pub fn save_config(config: &Config) -> io::Result<()> {
+    match config_path() {
+        Some(path) => save_config_to(&path, config),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no config directory on this platform")),
+    }
+}
+
+pub fn save_config_to(path: &Path, config: &Config) -> io::Result<()> {
+    let text = toml::to_string(config).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, text)
+}
+
+/// The database location used when nothing else is configured,
+/// e.g. `~/.local/share/counter_app/count.db` on Linux.
+pub fn default_db_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_DIR).join(DB_FILE))
+}
+
+/// Picks the database path in order of precedence:
+/// command-line flag, environment variable, config file, platform data directory.
+pub fn resolve_db_path(flag: Option<PathBuf>, env: Option<OsString>, config: &Config, default: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = flag {
+        return path;
+    }
+    if let Some(path) = env.filter(|p| !p.is_empty()) {
+        return PathBuf::from(path);
+    }
+    if let Some(path) = &config.db_path {
+        return path.clone();
+    }
+    default.unwrap_or_else(|| PathBuf::from(DB_FILE))
+}
+
+/// Moves a database left in the working directory by older versions to `target`.
+/// Does nothing if `target` already exists. Returns whether a file was moved.
+pub fn migrate_legacy_db(legacy: &Path, target: &Path) -> io::Result<bool> {
+    if target.exists() || !legacy.is_file() {
+        return Ok(false);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(legacy, target).is_err() {
+        // rename fails across file systems, so fall back to copying.
+        fs::copy(legacy, target)?;
+        fs::remove_file(legacy)?;
+    }
+    Ok(true)
+}
+
+/// Resolves the database path and makes sure its directory exists.
+/// A `count.db` in the working directory is moved over the first time the default location is used.
+pub fn locate_database(flag: Option<PathBuf>) -> io::Result<PathBuf> {
+    let default = default_db_path();
+    let path = resolve_db_path(flag, std::env::var_os(DB_ENV_VAR), &load_config(), default.clone());
+    if Some(&path) == default.as_ref() && migrate_legacy_db(Path::new(DB_FILE), &path)? {
+        println!("Moved {:?} to {:?}", DB_FILE, path);
+    }
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{migrate_legacy_db, resolve_db_path, save_config_to, Config};
+    use crate::utils::hotkey_handler::{default_bindings, default_repl_bindings, Action, Binding};
+
+    fn config_with(path: &str) -> Config {
+        Config { db_path: Some(PathBuf::from(path)), ..Config::default() }
+    }
+
+    #[test]
+    fn test_flag_takes_precedence() {
+        let res = resolve_db_path(Some("flag.db".into()), Some("env.db".into()), &config_with("config.db"), Some("default.db".into()));
+        assert_eq!(PathBuf::from("flag.db"), res);
+    }
+
+    #[test]
+    fn test_env_before_config() {
+        let res = resolve_db_path(None, Some("env.db".into()), &config_with("config.db"), Some("default.db".into()));
+        assert_eq!(PathBuf::from("env.db"), res);
+    }
+
+    #[test]
+    fn test_empty_env_is_ignored() {
+        let res = resolve_db_path(None, Some("".into()), &config_with("config.db"), Some("default.db".into()));
+        assert_eq!(PathBuf::from("config.db"), res);
+    }
+
+    #[test]
+    fn test_falls_back_to_default() {
+        let res = resolve_db_path(None, None, &Config::default(), Some("default.db".into()));
+        assert_eq!(PathBuf::from("default.db"), res);
+    }
+
+    #[test]
+    fn test_config_parses_db_path() {
+        let config: Config = toml::from_str("db_path = \"/data/hunts.db\"").unwrap();
+        assert_eq!(config_with("/data/hunts.db"), config);
+    }
+
+    #[test]
+    fn test_missing_bindings_use_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(default_bindings(), config.bindings);
+        assert_eq!(default_repl_bindings(), config.repl_bindings);
+    }
+
+    #[test]
+    fn test_save_config_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app").join("config.toml");
+        let mut config = config_with("/data/hunts.db");
+        config.bindings.push(Binding { counter: Some("Ralts".to_owned()), ..Binding::new("F2", Action::IncrementCounter) });
+        config.bindings.push(Binding { amount: Some(5), shift: true, ..Binding::new("Home", Action::Increment) });
+
+        save_config_to(&path, &config).unwrap();
+        let loaded: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config, loaded);
+    }
+
+    #[test]
+    fn test_migrate_moves_legacy_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("count.db");
+        let target = dir.path().join("data").join("count.db");
+        fs::write(&legacy, b"counters").unwrap();
+
+        assert!(migrate_legacy_db(&legacy, &target).unwrap());
+        assert!(!legacy.exists());
+        assert_eq!(b"counters".to_vec(), fs::read(&target).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_keeps_existing_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("count.db");
+        let target = dir.path().join("target.db");
+        fs::write(&legacy, b"old").unwrap();
+        fs::write(&target, b"new").unwrap();
+
+        assert!(!migrate_legacy_db(&legacy, &target).unwrap());
+        assert!(legacy.exists());
+        assert_eq!(b"new".to_vec(), fs::read(&target).unwrap());
+    }
+}
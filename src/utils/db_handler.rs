@@ -1,203 +1,1226 @@
-use std::{io::{Write, SeekFrom, Seek, Read, ErrorKind}, fmt};
+use std::{fmt, io, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use rusqlite::{Connection, params, Result, Error, blob::Blob};
-use bytes::{Bytes, BytesMut, BufMut};
+use rusqlite::{Connection, ErrorCode, OptionalExtension, params, Transaction, TransactionBehavior};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use bytes::Bytes;
 
-const DB_PATH: &str = "count.db";
+use crate::utils::db_migrations::migrate;
+
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+/// How long a write waits for another connection (e.g. a second app instance) to finish.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Generous enough for animated GIFs and HOME renders.
+pub const MAX_SPRITE_SIZE: usize = 4 * 1024 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct Pokemon {
     pub id: i32,
     pub name: String,
     pub counter: i32,
+    /// Encounters since the chain was last broken, moves together with `counter`.
+    pub chain: i32,
+    pub status: HuntStatus,
+    /// How many encounters one press counts, e.g. 5 for hordes.
+    pub step: i32,
+    /// A press on any linked counter counts on all the active ones.
+    pub linked: bool,
+    /// Set once the hunt is no longer active.
+    pub result: Option<HuntResult>,
+    pub settings: HuntSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HuntStatus {
+    Active,
+    Found,
+    Abandoned,
+}
+
+impl HuntStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HuntStatus::Active => "active",
+            HuntStatus::Found => "found",
+            HuntStatus::Abandoned => "abandoned",
+        }
+    }
+}
+
+impl ToSql for HuntStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for HuntStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "active" => Ok(HuntStatus::Active),
+            "found" => Ok(HuntStatus::Found),
+            "abandoned" => Ok(HuntStatus::Abandoned),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// How a finished hunt ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HuntResult {
+    /// See [`now_millis`].
+    pub finished_at: i64,
+    pub final_count: i32,
+    /// The shiny odds per encounter in effect when the hunt ended, if known.
+    pub odds: Option<f64>,
+}
+
+/// The odds calculator selection saved with a counter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HuntSettings {
+    /// `None` until a game has been picked for this hunt.
+    pub game: Option<String>,
+    /// Keys of the enabled methods, e.g. `masuda` or `charm`.
+    pub methods: Vec<String>,
+    /// Only used by methods like the DexNav.
+    pub search_level: u32,
+}
+
+fn pokemon_from_row(row: &rusqlite::Row) -> rusqlite::Result<Pokemon> {
+    let finished_at: Option<i64> = row.get("finished_at")?;
+    let result = match finished_at {
+        Some(finished_at) => Some(HuntResult {
+            finished_at,
+            final_count: row.get("final_count")?,
+            odds: row.get("final_odds")?,
+        }),
+        None => None,
+    };
+    Ok(Pokemon {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        counter: row.get("count")?,
+        chain: row.get("chain")?,
+        status: row.get("status")?,
+        step: row.get("step")?,
+        linked: row.get("linked")?,
+        result,
+        settings: HuntSettings {
+            game: row.get("game")?,
+            methods: row.get::<_, String>("methods")?
+                .split(',')
+                .filter(|m| !m.is_empty())
+                .map(|m| m.to_owned())
+                .collect(),
+            search_level: row.get("search_level")?,
+        },
+    })
 }
 
 impl Pokemon {
+    /// Moves the chain by the same amount, the way the database does.
     pub fn update_counter(&mut self, new_counter: i32) {
+        self.chain = (self.chain + new_counter - self.counter).max(0);
         self.counter = new_counter;
     }
 }
 
-// type Result<T> = std::result::Result<T, DBError>;
-
-// #[derive(Debug, Clone)]
-// struct DBError {
-//     msg: String,
-// }
-
-// impl fmt::Display for DBError {
-//     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-//         write!(f, "{:?}", self.msg)
-//     }
-// }
-
-
-pub fn connect() -> Result<Connection> {
-    let conn = Connection::open(DB_PATH)?;
-    conn.execute(
-        "create table if not exists counters (
-             name text primary key,
-             count integer not null
-         )",
-        [],
-    )?;
-    conn.execute(
-        "create table if not exists sprites (
-             name text primary key,
-             img blob not null
-        )", 
-        [],
-    )?;
-    Ok(conn)
-}
-
-pub fn add_new_counter(name: &str) -> Result<()>{
-    let conn = Connection::open(DB_PATH)?;
-    match conn.execute(
-        "INSERT INTO counters (name, count) VALUES (?1, ?2)",
-            params![name, 0]
-    ) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err),
-    }
-}
-
-pub fn increment_counter(id: i32) -> Result<i32>{
-    add_counter(id, 1)
-}
-
-pub fn add_counter(id: i32, amnt: i32) -> Result<i32>{
-    if id == -1 {
-        return Err(rusqlite::Error::InvalidQuery);
-    }
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare(
-        "SELECT count FROM counters WHERE rowid = ?1")?;
-    let counter 
-        = stmt.query_row(params![id], |row| {
-         row.get(0)
-        });
-    let count:i32 = match counter {
-        Ok(r) => r,
-        Err(e) => return Err(e),
-    };
-    conn.execute(
-            "UPDATE counters SET count = ?1 WHERE rowid = ?2",
-                params![count + amnt, id]
-    )?;
-    Ok(count+amnt)
-}
-
-pub fn read_counter(id: i32)-> Result<Pokemon> {
-    if id == -1 {
-        return Err(rusqlite::Error::InvalidQuery);
-    }
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare(
-        "SELECT name, count FROM counters WHERE rowid = ?1")?;
-    let counter 
-        = stmt.query_row(params![id], |row| {
-         Ok(Pokemon {
-            id,
-            name: row.get(0)?,
-            counter: row.get(1)?,
-         })
-        });
-    let counter = match counter{
-        Ok(counter) => counter,
-        Err(e) => return Err(e),
-    };
-    Ok(counter)
-}
-
-pub fn get_row_id(name: &str)-> Result<i32> {
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare(
-        "SELECT rowid FROM counters WHERE name = ?1")?;
-    let counter 
-        = stmt.query_row(params![name], |row| {
-         row.get(0)
-        });
-    let counter = match counter{
-        Ok(counter) => counter,
-        Err(e) => return Err(e),
-    };
-    Ok(counter)
-}
-
-pub fn get_all_counters() -> Result<Vec<Pokemon>>{
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare(
-        "SELECT rowid, name, count FROM counters")?;
-    let result = stmt.query_map([], |row| {
-        Ok(Pokemon{
-            id: row.get(0)?,
-            name: row.get(1)?,
-            counter: row.get(2)?,
-        })
-    })?;
-    let mut counters:Vec<Pokemon> = Vec::new();
-    for counter in result{
-        if let Ok(c) = counter {counters.push(c); } // destructuring but ignoring all errors.
-    };
-    Ok(counters)
-}
-
-pub fn get_sprite_row_id(name: &str)-> Result<i32> {
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare(
-        "SELECT rowid FROM sprites WHERE name = ?1")?;
-    let id 
-        = stmt.query_row(params![name], |row| {
-         row.get(0)
-        });
-    let id = match id{
-        Ok(id) => id,
-        Err(e) => return Err(e),
-    };
-    Ok(id)
-}
-
-pub fn save_sprite(name: &str, img: Bytes) -> Result<Bytes> {
-    let conn = Connection::open(DB_PATH)?;
-    let _ = conn.execute(
-        "INSERT OR IGNORE INTO sprites (name, img) VALUES (?1, ZEROBLOB(22528)) RETURNING rowid",
-            params![name]
-    );
-    let rowid = match get_sprite_row_id(&name) {
-        Ok(rowid) => rowid,
-        Err(err) => {println!("hi: {:?}", name);return Err(err);},
-    };
-    let mut blob = conn.blob_open(rusqlite::DatabaseName::Main,"sprites", "img", rowid.into(), false)?;
-    match blob.write(&img) {
-        Ok(_) => Ok(img),
-        Err(_) => {println!("Failed.......");Ok(img)},
+/// What caused a change to a counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Button,
+    Hotkey,
+    Cli,
+    /// Counts that existed before the encounter log was introduced.
+    Import,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Button => "button",
+            Source::Hotkey => "hotkey",
+            Source::Cli => "cli",
+            Source::Import => "import",
+        }
     }
 }
 
-pub fn get_sprite(name: &str) -> Result<Bytes> {
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare("SELECT rowid FROM sprites WHERE name = ?1")?;
-    let rowid:i32 = match stmt.query_row(params![name], |row| {
-        row.get(0)
-    }) {
-        Ok(r) => r,
-        Err(err) => return Err(err),
-    };
-    let mut blob = match conn.blob_open(rusqlite::DatabaseName::Main, "sprites", "img", rowid.into(), true) {
-        Ok(r) => r,
-        Err(err) => return Err(err),
+impl ToSql for Source {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Source {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "button" => Ok(Source::Button),
+            "hotkey" => Ok(Source::Hotkey),
+            "cli" => Ok(Source::Cli),
+            "import" => Ok(Source::Import),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Odds in effect from `start_count` encounters into a hunt until the next change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OddsChange {
+    pub start_count: i32,
+    pub odds: f64,
+}
+
+/// One change to a counter in the encounter log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encounter {
+    pub id: i64,
+    pub counter_id: i32,
+    /// See [`now_millis`].
+    pub timestamp: i64,
+    pub delta: i32,
+    pub source: Source,
+    /// Reverted by an undo and waiting to be redone.
+    pub undone: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    pub img: Bytes,
+    pub content_type: Option<String>,
+    pub source_url: Option<String>,
+    /// When the sprite was downloaded, see [`now_millis`].
+    pub fetched_at: Option<i64>,
+}
+
+pub type Result<T> = std::result::Result<T, DBError>;
+
+#[derive(Debug)]
+pub enum DBError {
+    /// The named thing, e.g. `Counter "pikachu"`, is not in the database.
+    NotFound(String),
+    /// A counter with this name already exists.
+    Duplicate(String),
+    /// Counter names can't be empty or only whitespace.
+    EmptyName,
+    /// An operation needed a loaded counter but none was selected.
+    NoActiveCounter,
+    /// The change would have taken the count below zero.
+    NegativeCount,
+    /// Steps must count at least one encounter.
+    InvalidStep(i32),
+    SpriteTooLarge { size: usize, max: usize },
+    /// The database was written by a newer version of the app.
+    SchemaTooNew { found: usize, supported: usize },
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DBError::NotFound(what) => write!(f, "{} does not exist.", what),
+            DBError::Duplicate(name) => write!(f, "Counter with name {:?} already exists.", name),
+            DBError::EmptyName => write!(f, "Counter name can't be empty."),
+            DBError::NoActiveCounter => write!(f, "No counter loaded."),
+            DBError::NegativeCount => write!(f, "Counts can't go below zero."),
+            DBError::InvalidStep(step) => write!(f, "Step must be at least 1, got {}.", step),
+            DBError::SpriteTooLarge { size, max } => write!(f, "Sprite is {} bytes, the limit is {} bytes.", size, max),
+            DBError::SchemaTooNew { found, supported } => write!(f, "Database schema version {} is newer than this app supports ({}). Please update the app.", found, supported),
+            DBError::Io(err) => write!(f, "Could not access the database: {}", err),
+            DBError::Sqlite(err) => write!(f, "Database error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DBError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DBError::Io(err) => Some(err),
+            DBError::Sqlite(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DBError {
+    fn from(err: rusqlite::Error) -> Self {
+        DBError::Sqlite(err)
+    }
+}
+
+impl From<io::Error> for DBError {
+    fn from(err: io::Error) -> Self {
+        DBError::Io(err)
+    }
+}
+
+fn counter_not_found(id: i32) -> DBError {
+    DBError::NotFound(format!("Counter with id {}", id))
+}
+
+/// Owns the connection to the counter database. Statements are prepared once
+/// and reused through the connection's statement cache.
+pub struct CounterStore {
+    conn: Connection,
+}
+
+impl CounterStore {
+    /// Opens (or creates) the database at `path` and applies pending migrations.
+    pub fn open(path: &Path) -> Result<Self> {
+        CounterStore::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        CounterStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        migrate(&mut conn)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Ok(CounterStore { conn })
+    }
+
+    pub fn add_new_counter(&self, name: &str) -> Result<()>{
+        check_name(name)?;
+        let res = self.conn.prepare_cached(
+            "INSERT INTO counters (name, count) VALUES (?1, ?2)")?
+            .execute(params![name, 0]);
+        match res {
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => {
+                Err(DBError::Duplicate(name.to_owned()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Adds one step to the counter.
+    pub fn increment_counter(&mut self, id: i32, source: Source) -> Result<i32>{
+        let step = self.read_step(id)?;
+        self.add_counter(id, step, source)
+    }
+
+    /// Adds `amnt` (which may be negative) to the counter, logs it as an encounter and
+    /// returns the new count. The read and the write happen in one statement, so
+    /// concurrent presses can never overwrite each other.
+    pub fn add_counter(&mut self, id: i32, amnt: i32, source: Source) -> Result<i32>{
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let count = apply_delta(&tx, id, amnt, source, None)?;
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Adds each amount to its counter in one transaction, so either every counter
+    /// changes or none do. Returns the new counts in the same order.
+    /// The changes are logged as one batch, which undo and redo treat as a single step.
+    pub fn add_to_counters(&mut self, changes: &[(i32, i32)], source: Source) -> Result<Vec<i32>> {
+        if changes.is_empty() {
+            return Err(DBError::NoActiveCounter);
+        }
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let batch: i64 = tx.prepare_cached("SELECT coalesce(max(batch), 0) + 1 FROM encounters")?
+            .query_row([], |row| row.get(0))?;
+        let counts = changes.iter()
+            .map(|&(id, amnt)| apply_delta(&tx, id, amnt, source, Some(batch)))
+            .collect::<Result<Vec<_>>>()?;
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    /// Sets the counter to `value`, logged as a single change so it can be undone in one step.
+    pub fn set_counter(&mut self, id: i32, value: i32, source: Source) -> Result<i32>{
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        if value < 0 {
+            return Err(DBError::NegativeCount);
+        }
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current: i32 = tx.prepare_cached(
+            "SELECT count FROM counters WHERE id = ?1")?
+            .query_row(params![id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))?;
+        if current == value {
+            return Ok(value);
+        }
+        let count = apply_delta(&tx, id, value - current, source, None)?;
+        tx.commit()?;
+        Ok(count)
+    }
+
+    pub fn reset_counter(&mut self, id: i32, source: Source) -> Result<i32>{
+        self.set_counter(id, 0, source)
+    }
+
+    /// Reverts the latest change to the counter, returning the new count,
+    /// or `None` if there is nothing left to undo.
+    pub fn undo(&mut self, id: i32) -> Result<Option<i32>> {
+        Ok(count_of(self.undo_batch(id)?, id))
+    }
+
+    /// Re-applies the most recently undone change, returning the new count,
+    /// or `None` if there is nothing to redo.
+    pub fn redo(&mut self, id: i32) -> Result<Option<i32>> {
+        Ok(count_of(self.redo_batch(id)?, id))
+    }
+
+    /// Reverts the latest change to the counter along with the rest of its batch,
+    /// e.g. a press counted on every linked counter. Returns `(counter id, new count)`
+    /// for each counter that changed, empty if there is nothing left to undo.
+    pub fn undo_batch(&mut self, id: i32) -> Result<Vec<(i32, i32)>> {
+        self.toggle_batch(id,
+            "SELECT id, batch FROM encounters WHERE counter_id = ?1 AND undone = 0 ORDER BY id DESC LIMIT 1",
+            true)
+    }
+
+    /// Re-applies the most recently undone change to the counter along with the rest of its batch.
+    pub fn redo_batch(&mut self, id: i32) -> Result<Vec<(i32, i32)>> {
+        self.toggle_batch(id,
+            "SELECT id, batch FROM encounters WHERE counter_id = ?1 AND undone = 1 ORDER BY id ASC LIMIT 1",
+            false)
+    }
+
+    fn toggle_batch(&mut self, id: i32, select: &str, undone: bool) -> Result<Vec<(i32, i32)>> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let encounter: Option<(i64, Option<i64>)> = tx.prepare_cached(select)?
+            .query_row(params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        let rows = match encounter {
+            None => return Ok(vec![]),
+            Some((_, Some(batch))) => tx.prepare_cached(
+                "SELECT id, counter_id, delta FROM encounters WHERE batch = ?1 AND undone = ?2 ORDER BY id")?
+                .query_map(params![batch, !undone], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<Vec<(i64, i32, i32)>>>()?,
+            Some((encounter_id, None)) => tx.prepare_cached(
+                "SELECT id, counter_id, delta FROM encounters WHERE id = ?1")?
+                .query_map(params![encounter_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<Vec<(i64, i32, i32)>>>()?,
+        };
+        let mut counts = Vec::with_capacity(rows.len());
+        for (encounter_id, counter_id, delta) in rows {
+            tx.prepare_cached("UPDATE encounters SET undone = ?1 WHERE id = ?2")?
+                .execute(params![undone, encounter_id])?;
+            let delta = if undone { -delta } else { delta };
+            let count: i32 = tx.prepare_cached(
+                "UPDATE counters SET count = count + ?1, chain = max(chain + ?1, 0) WHERE id = ?2 RETURNING count")?
+                .query_row(params![delta, counter_id], |row| row.get(0))?;
+            counts.push((counter_id, count));
+        }
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    /// The encounter log of a counter, oldest first.
+    pub fn get_encounters(&self, id: i32) -> Result<Vec<Encounter>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, counter_id, ts, delta, source, undone FROM encounters WHERE counter_id = ?1 ORDER BY id")?;
+        let result = stmt.query_map(params![id], |row| {
+            Ok(Encounter {
+                id: row.get(0)?,
+                counter_id: row.get(1)?,
+                timestamp: row.get(2)?,
+                delta: row.get(3)?,
+                source: row.get(4)?,
+                undone: row.get(5)?,
+            })
+        })?;
+        Ok(result.collect::<rusqlite::Result<Vec<Encounter>>>()?)
+    }
+
+    /// Rebuilds the count from the encounter log, e.g. after editing the log by hand.
+    pub fn recompute_counter(&mut self, id: i32) -> Result<i32> {
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let count: Option<i32> = tx.prepare_cached(
+            "UPDATE counters SET count = (SELECT coalesce(sum(delta), 0) FROM encounters WHERE counter_id = ?1 AND undone = 0)
+             WHERE id = ?1 RETURNING count")?
+            .query_row(params![id], |row| row.get(0))
+            .optional()?;
+        let count = count.ok_or_else(|| counter_not_found(id))?;
+        tx.commit()?;
+        Ok(count)
+    }
+
+    pub fn read_counter(&self, id: i32)-> Result<Pokemon> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, name, count, chain, status, finished_at, final_count, final_odds, game, methods, search_level, step, linked FROM counters WHERE id = ?1")?;
+        stmt.query_row(params![id], pokemon_from_row).optional()?
+            .ok_or_else(|| counter_not_found(id))
+    }
+
+    /// Ends a hunt, recording the count and odds at this moment.
+    /// The counter is kept so it can be shown in the hunt history.
+    pub fn finish_hunt(&mut self, id: i32, status: HuntStatus, odds: Option<f64>) -> Result<Pokemon> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        self.conn.prepare_cached(
+            "UPDATE counters SET status = ?1, finished_at = ?2, final_count = count, final_odds = ?3
+             WHERE id = ?4 RETURNING id, name, count, chain, status, finished_at, final_count, final_odds, game, methods, search_level, step, linked")?
+            .query_row(params![status, now_millis(), odds, id], pokemon_from_row)
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))
+    }
+
+    /// Makes a finished hunt active again.
+    pub fn reopen_hunt(&mut self, id: i32) -> Result<Pokemon> {
+        self.conn.prepare_cached(
+            "UPDATE counters SET status = ?1, finished_at = NULL, final_count = NULL, final_odds = NULL
+             WHERE id = ?2 RETURNING id, name, count, chain, status, finished_at, final_count, final_odds, game, methods, search_level, step, linked")?
+            .query_row(params![HuntStatus::Active, id], pokemon_from_row)
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))
+    }
+
+    /// Sets the chain length without touching the encounter count, e.g. to 0 when the chain breaks.
+    pub fn set_chain(&mut self, id: i32, value: i32) -> Result<i32> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        if value < 0 {
+            return Err(DBError::NegativeCount);
+        }
+        self.conn.prepare_cached("UPDATE counters SET chain = ?1 WHERE id = ?2 RETURNING chain")?
+            .query_row(params![value, id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))
+    }
+
+    pub fn read_step(&self, id: i32) -> Result<i32> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        self.conn.prepare_cached("SELECT step FROM counters WHERE id = ?1")?
+            .query_row(params![id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))
+    }
+
+    pub fn set_step(&mut self, id: i32, step: i32) -> Result<i32> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        if step < 1 {
+            return Err(DBError::InvalidStep(step));
+        }
+        self.conn.prepare_cached("UPDATE counters SET step = ?1 WHERE id = ?2 RETURNING step")?
+            .query_row(params![step, id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))
+    }
+
+    pub fn set_linked(&mut self, id: i32, linked: bool) -> Result<()> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        let changed = self.conn.prepare_cached("UPDATE counters SET linked = ?1 WHERE id = ?2")?
+            .execute(params![linked, id])?;
+        if changed == 0 {
+            return Err(counter_not_found(id));
+        }
+        Ok(())
+    }
+
+    /// The counters a press on `id` goes to: `id` itself followed by the other
+    /// active linked counters if it is linked, otherwise just `id`.
+    pub fn linked_group(&self, id: i32) -> Result<Vec<i32>> {
+        if id == -1 {
+            return Err(DBError::NoActiveCounter);
+        }
+        let counter = self.read_counter(id)?;
+        if !counter.linked || counter.status != HuntStatus::Active {
+            return Ok(vec![id]);
+        }
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id FROM counters WHERE linked = 1 AND status = ?1 AND id != ?2 ORDER BY id")?;
+        let others = stmt.query_map(params![HuntStatus::Active, id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i32>>>()?;
+        Ok(std::iter::once(id).chain(others).collect())
+    }
+
+    /// Records that the odds changed at `start_count`. Changes recorded past it
+    /// belong to encounters that were taken back, so they are dropped.
+    pub fn record_odds(&mut self, id: i32, start_count: i32, odds: f64) -> Result<()> {
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.prepare_cached("DELETE FROM odds_history WHERE counter_id = ?1 AND start_count > ?2")?
+            .execute(params![id, start_count])?;
+        tx.prepare_cached(
+            "INSERT OR REPLACE INTO odds_history (counter_id, start_count, odds) VALUES (?1, ?2, ?3)")?
+            .execute(params![id, start_count, odds])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_odds_history(&self, id: i32) -> Result<Vec<OddsChange>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT start_count, odds FROM odds_history WHERE counter_id = ?1 ORDER BY start_count")?;
+        let rows = stmt.query_map(params![id], |row| Ok(OddsChange {
+            start_count: row.get(0)?,
+            odds: row.get(1)?,
+        }))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Remembers which game and methods the odds calculator uses for this counter.
+    pub fn save_hunt_settings(&mut self, id: i32, settings: &HuntSettings) -> Result<()> {
+        let updated = self.conn.prepare_cached("UPDATE counters SET game = ?1, methods = ?2, search_level = ?3 WHERE id = ?4")?
+            .execute(params![settings.game, settings.methods.join(","), settings.search_level, id])?;
+        if updated == 0 {
+            return Err(counter_not_found(id));
+        }
+        Ok(())
+    }
+
+    /// Renames a counter. Its cached sprite moves along unless the new name already has one.
+    pub fn rename_counter(&mut self, id: i32, new_name: &str) -> Result<()> {
+        check_name(new_name)?;
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let old_name: String = tx.prepare_cached("SELECT name FROM counters WHERE id = ?1")?
+            .query_row(params![id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))?;
+        let res = tx.prepare_cached("UPDATE counters SET name = ?1 WHERE id = ?2")?
+            .execute(params![new_name, id]);
+        match res {
+            Ok(_) => {}
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => {
+                return Err(DBError::Duplicate(new_name.to_owned()));
+            }
+            Err(err) => return Err(err.into()),
+        }
+        tx.prepare_cached("UPDATE OR IGNORE sprites SET name = ?1 WHERE name = ?2")?
+            .execute(params![new_name, old_name])?;
+        tx.prepare_cached("DELETE FROM sprites WHERE name = ?1")?
+            .execute(params![old_name])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Deletes a counter together with its encounter log and cached sprite.
+    pub fn delete_counter(&mut self, id: i32) -> Result<()> {
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let name: String = tx.prepare_cached("DELETE FROM counters WHERE id = ?1 RETURNING name")?
+            .query_row(params![id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| counter_not_found(id))?;
+        tx.prepare_cached("DELETE FROM encounters WHERE counter_id = ?1")?
+            .execute(params![id])?;
+        tx.prepare_cached("DELETE FROM odds_history WHERE counter_id = ?1")?
+            .execute(params![id])?;
+        tx.prepare_cached("DELETE FROM sprites WHERE name = ?1")?
+            .execute(params![name])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_row_id(&self, name: &str)-> Result<i32> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id FROM counters WHERE name = ?1")?;
+        stmt.query_row(params![name], |row| {
+             row.get(0)
+            }).optional()?
+            .ok_or_else(|| DBError::NotFound(format!("Counter with name {:?}", name)))
+    }
+
+    pub fn get_all_counters(&self) -> Result<Vec<Pokemon>>{
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, name, count, chain, status, finished_at, final_count, final_odds, game, methods, search_level, step, linked FROM counters")?;
+        let result = stmt.query_map([], pokemon_from_row)?;
+        let counters:Vec<Pokemon> = result.flatten().collect(); // ignores rows that fail to parse.
+        Ok(counters)
+    }
+
+    /// Caches a sprite, replacing any earlier copy stored under the same name.
+    pub fn save_sprite(&self, name: &str, sprite: &Sprite) -> Result<()> {
+        if sprite.img.len() > MAX_SPRITE_SIZE {
+            return Err(DBError::SpriteTooLarge { size: sprite.img.len(), max: MAX_SPRITE_SIZE });
+        }
+        self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO sprites (name, img, content_type, source_url, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)")?
+            .execute(params![name, &sprite.img[..], sprite.content_type, sprite.source_url, sprite.fetched_at])?;
+        Ok(())
+    }
+
+    /// Drops a cached sprite, e.g. one that turned out to be unreadable.
+    pub fn delete_sprite(&self, name: &str) -> Result<()> {
+        self.conn.prepare_cached("DELETE FROM sprites WHERE name = ?1")?.execute(params![name])?;
+        Ok(())
+    }
+
+    pub fn get_sprite(&self, name: &str) -> Result<Sprite> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT img, content_type, source_url, fetched_at FROM sprites WHERE name = ?1")?;
+        stmt.query_row(params![name], |row| {
+            Ok(Sprite {
+                img: Bytes::from(row.get::<_, Vec<u8>>(0)?),
+                content_type: row.get(1)?,
+                source_url: row.get(2)?,
+                fetched_at: row.get(3)?,
+            })
+        }).optional()?
+            .ok_or_else(|| DBError::NotFound(format!("Sprite for {:?}", name)))
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        return Err(DBError::EmptyName);
+    }
+    Ok(())
+}
+
+/// Adds `delta` to a counter and logs it, refusing changes that would make the count negative.
+fn apply_delta(tx: &Transaction, id: i32, delta: i32, source: Source, batch: Option<i64>) -> Result<i32> {
+    let count: Option<i32> = tx.prepare_cached(
+        "UPDATE counters SET count = count + ?1, chain = max(chain + ?1, 0)
+         WHERE id = ?2 AND count + ?1 >= 0 RETURNING count")?
+        .query_row(params![delta, id], |row| row.get(0))
+        .optional()?;
+    let count = match count {
+        Some(c) => c,
+        None => {
+            let exists: Option<i32> = tx.prepare_cached("SELECT id FROM counters WHERE id = ?1")?
+                .query_row(params![id], |row| row.get(0))
+                .optional()?;
+            return Err(if exists.is_some() { DBError::NegativeCount } else { counter_not_found(id) });
+        }
     };
-    blob.seek(SeekFrom::Start(0));
-    let mut buf = [0u8; 22528];
-    let bytes_read = blob.read(&mut buf[..]);
-    match bytes_read {
-        Ok(size) => {
-            let mut bytes = BytesMut::new();
-            bytes.reserve(size);
-            bytes.put_slice(&buf);
-            return Ok(bytes.freeze());
-        },
-        Err(err) => Err(rusqlite::Error::BlobSizeError),
+    // a new change makes anything that was undone impossible to redo.
+    tx.prepare_cached(
+        "DELETE FROM encounters WHERE counter_id = ?1 AND undone = 1")?
+        .execute(params![id])?;
+    tx.prepare_cached(
+        "INSERT INTO encounters (counter_id, ts, delta, source, batch) VALUES (?1, ?2, ?3, ?4, ?5)")?
+        .execute(params![id, now_millis(), delta, source, batch])?;
+    Ok(count)
+}
+
+/// The new count of `id` among the counts changed by an undo or redo.
+fn count_of(counts: Vec<(i32, i32)>, id: i32) -> Option<i32> {
+    counts.into_iter().find(|(counter_id, _)| *counter_id == id).map(|(_, count)| count)
+}
+
+/// Guesses the MIME type from the file signature.
+pub fn sniff_content_type(img: &[u8]) -> Option<&'static str> {
+    if img.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if img.starts_with(b"GIF87a") || img.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if img.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else {
+        None
+    }
+}
+
+/// The MIME type of a downloaded sprite, or `None` if it is not an image,
+/// e.g. an HTML error page. Without a header the file signature decides.
+pub fn image_content_type(header: Option<&str>, img: &[u8]) -> Option<String> {
+    match header {
+        Some(t) if t.trim().to_ascii_lowercase().starts_with("image/") => Some(t.trim().to_owned()),
+        Some(_) => None,
+        None => sniff_content_type(img).map(|t| t.to_owned()),
+    }
+}
+
+/// Milliseconds since the Unix epoch, the unit used for every timestamp in the database.
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Formats a timestamp from [`now_millis`] as a UTC `YYYY-MM-DD` date.
+pub fn format_date(millis: i64) -> String {
+    // days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = millis.div_euclid(86_400_000) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use bytes::Bytes;
+
+    use super::{format_date, image_content_type, CounterStore, DBError, HuntSettings, HuntStatus, Source, Sprite, MAX_SPRITE_SIZE};
+
+    #[test]
+    fn test_add_and_read_counter() {
+        let store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("pikachu").unwrap();
+        let id = store.get_row_id("pikachu").unwrap();
+        let pkmn = store.read_counter(id).unwrap();
+        assert_eq!("pikachu", pkmn.name);
+        assert_eq!(0, pkmn.counter);
+    }
+
+    #[test]
+    fn test_add_duplicate_counter_fails() {
+        let store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("pikachu").unwrap();
+        assert!(matches!(store.add_new_counter("pikachu"), Err(DBError::Duplicate(name)) if name == "pikachu"));
+    }
+
+    #[test]
+    fn test_add_counter() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        assert_eq!(1, store.increment_counter(id, Source::Button).unwrap());
+        assert_eq!(6, store.add_counter(id, 5, Source::Cli).unwrap());
+        assert_eq!(6, store.read_counter(id).unwrap().counter);
+    }
+
+    #[test]
+    fn test_add_counter_logs_encounters() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.increment_counter(id, Source::Hotkey).unwrap();
+        store.add_counter(id, 4, Source::Cli).unwrap();
+
+        let log = store.get_encounters(id).unwrap();
+        let entries: Vec<(i32, Source)> = log.iter().map(|e| (e.delta, e.source)).collect();
+        assert_eq!(vec![(1, Source::Hotkey), (4, Source::Cli)], entries);
+        assert!(log[0].timestamp <= log[1].timestamp);
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.increment_counter(id, Source::Button).unwrap();
+        store.add_counter(id, 5, Source::Cli).unwrap();
+
+        assert_eq!(Some(1), store.undo(id).unwrap());
+        assert_eq!(Some(0), store.undo(id).unwrap());
+        assert_eq!(None, store.undo(id).unwrap());
+        assert_eq!(Some(1), store.redo(id).unwrap());
+        assert_eq!(Some(6), store.redo(id).unwrap());
+        assert_eq!(None, store.redo(id).unwrap());
+        assert_eq!(6, store.recompute_counter(id).unwrap());
+    }
+
+    #[test]
+    fn test_new_change_discards_redo() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.add_counter(id, 5, Source::Cli).unwrap();
+        store.undo(id).unwrap();
+        store.increment_counter(id, Source::Hotkey).unwrap();
+
+        assert_eq!(None, store.redo(id).unwrap());
+        let log = store.get_encounters(id).unwrap();
+        assert_eq!(1, log.len());
+        assert!(!log[0].undone);
+    }
+
+    #[test]
+    fn test_undo_is_per_counter() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        store.add_new_counter("ditto").unwrap();
+        let eevee = store.get_row_id("eevee").unwrap();
+        let ditto = store.get_row_id("ditto").unwrap();
+        store.add_counter(eevee, 2, Source::Button).unwrap();
+        store.add_counter(ditto, 3, Source::Button).unwrap();
+
+        assert_eq!(Some(0), store.undo(eevee).unwrap());
+        assert_eq!(3, store.read_counter(ditto).unwrap().counter);
+    }
+
+    #[test]
+    fn test_decrement_never_goes_negative() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.add_counter(id, 2, Source::Button).unwrap();
+        assert_eq!(1, store.add_counter(id, -1, Source::Button).unwrap());
+        assert!(matches!(store.add_counter(id, -2, Source::Button), Err(DBError::NegativeCount)));
+        assert_eq!(1, store.read_counter(id).unwrap().counter);
+        assert_eq!(2, store.get_encounters(id).unwrap().len());
+    }
+
+    #[test]
+    fn test_set_and_reset_counter() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        assert_eq!(120, store.set_counter(id, 120, Source::Cli).unwrap());
+        assert!(matches!(store.set_counter(id, -5, Source::Cli), Err(DBError::NegativeCount)));
+        assert_eq!(0, store.reset_counter(id, Source::Button).unwrap());
+
+        assert_eq!(Some(120), store.undo(id).unwrap());
+        assert_eq!(Some(0), store.undo(id).unwrap());
+        assert_eq!(0, store.recompute_counter(id).unwrap());
+    }
+
+    #[test]
+    fn test_set_missing_counter_fails() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        assert!(matches!(store.set_counter(7, 3, Source::Cli), Err(DBError::NotFound(_))));
+        assert!(matches!(store.add_counter(7, -1, Source::Cli), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_rename_counter_moves_sprite() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("pikachuu").unwrap();
+        let id = store.get_row_id("pikachuu").unwrap();
+        store.add_counter(id, 3, Source::Button).unwrap();
+        store.save_sprite("pikachuu", &sprite(b"GIF89a".to_vec())).unwrap();
+
+        store.rename_counter(id, "pikachu").unwrap();
+        assert_eq!(id, store.get_row_id("pikachu").unwrap());
+        assert_eq!(3, store.read_counter(id).unwrap().counter);
+        assert!(store.get_sprite("pikachu").is_ok());
+        assert!(matches!(store.get_sprite("pikachuu"), Err(DBError::NotFound(_))));
+        assert_eq!(1, store.get_encounters(id).unwrap().len());
+    }
+
+    #[test]
+    fn test_rename_keeps_existing_sprite() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("pikachuu").unwrap();
+        let id = store.get_row_id("pikachuu").unwrap();
+        store.save_sprite("pikachuu", &sprite(b"old".to_vec())).unwrap();
+        store.save_sprite("pikachu", &sprite(b"new".to_vec())).unwrap();
+
+        store.rename_counter(id, "pikachu").unwrap();
+        assert_eq!(&b"new"[..], &store.get_sprite("pikachu").unwrap().img[..]);
+        assert!(store.get_sprite("pikachuu").is_err());
+    }
+
+    #[test]
+    fn test_blank_names_are_rejected() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        assert!(matches!(store.add_new_counter(""), Err(DBError::EmptyName)));
+        assert!(matches!(store.add_new_counter(" \t"), Err(DBError::EmptyName)));
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        assert!(matches!(store.rename_counter(id, "  "), Err(DBError::EmptyName)));
+        assert_eq!("eevee", store.read_counter(id).unwrap().name);
+    }
+
+    #[test]
+    fn test_rename_to_existing_name_fails() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        store.add_new_counter("ditto").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        assert!(matches!(store.rename_counter(id, "ditto"), Err(DBError::Duplicate(_))));
+        assert_eq!("eevee", store.read_counter(id).unwrap().name);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_delete_counter() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.add_counter(id, 3, Source::Button).unwrap();
+        store.save_sprite("eevee", &sprite(b"GIF89a".to_vec())).unwrap();
+
+        store.delete_counter(id).unwrap();
+        assert!(matches!(store.read_counter(id), Err(DBError::NotFound(_))));
+        assert!(store.get_sprite("eevee").is_err());
+        assert!(store.get_encounters(id).unwrap().is_empty());
+        assert!(matches!(store.delete_counter(id), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_finish_and_reopen_hunt() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.add_counter(id, 812, Source::Button).unwrap();
+        assert_eq!(HuntStatus::Active, store.read_counter(id).unwrap().status);
+
+        let found = store.finish_hunt(id, HuntStatus::Found, Some(3.0 / 4096.0)).unwrap();
+        assert_eq!(HuntStatus::Found, found.status);
+        let result = found.result.unwrap();
+        assert_eq!(812, result.final_count);
+        assert_eq!(Some(3.0 / 4096.0), result.odds);
+        assert_eq!(HuntStatus::Found, store.get_all_counters().unwrap()[0].status);
+
+        let reopened = store.reopen_hunt(id).unwrap();
+        assert_eq!(HuntStatus::Active, reopened.status);
+        assert!(reopened.result.is_none());
+        assert_eq!(812, reopened.counter);
+    }
+
+    #[test]
+    fn test_increment_uses_step() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("seviper").unwrap();
+        let id = store.get_row_id("seviper").unwrap();
+        assert_eq!(1, store.read_counter(id).unwrap().step);
+        assert_eq!(1, store.increment_counter(id, Source::Cli).unwrap());
+
+        assert_eq!(5, store.set_step(id, 5).unwrap());
+        assert_eq!(6, store.increment_counter(id, Source::Hotkey).unwrap());
+        assert_eq!(Some(1), store.undo(id).unwrap());
+
+        assert!(matches!(store.set_step(id, 0), Err(DBError::InvalidStep(0))));
+        assert!(matches!(store.read_step(id + 1), Err(DBError::NotFound(_))));
+        assert_eq!(5, store.read_counter(id).unwrap().step);
+    }
+
+    #[test]
+    fn test_linked_counters_count_together() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        for name in ["ralts", "eevee", "zorua"] {
+            store.add_new_counter(name).unwrap();
+        }
+        let (ralts, eevee, zorua) = (store.get_row_id("ralts").unwrap(), store.get_row_id("eevee").unwrap(), store.get_row_id("zorua").unwrap());
+        assert_eq!(vec![eevee], store.linked_group(eevee).unwrap());
+
+        store.set_linked(ralts, true).unwrap();
+        store.set_linked(eevee, true).unwrap();
+        store.set_linked(zorua, true).unwrap();
+        store.finish_hunt(ralts, HuntStatus::Found, None).unwrap();
+        assert!(store.read_counter(eevee).unwrap().linked);
+        assert_eq!(vec![zorua, eevee], store.linked_group(zorua).unwrap());
+        assert_eq!(vec![ralts], store.linked_group(ralts).unwrap());
+
+        assert_eq!(vec![2, 2], store.add_to_counters(&[(eevee, 2), (zorua, 2)], Source::Hotkey).unwrap());
+        // A change that fails leaves the others untouched too.
+        assert!(matches!(store.add_to_counters(&[(eevee, 1), (ralts, -5)], Source::Hotkey), Err(DBError::NegativeCount)));
+        assert_eq!(2, store.read_counter(eevee).unwrap().counter);
+        assert!(matches!(store.set_linked(zorua + 1, true), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_linked_press_is_undone_as_one() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        store.add_new_counter("zorua").unwrap();
+        let (eevee, zorua) = (store.get_row_id("eevee").unwrap(), store.get_row_id("zorua").unwrap());
+        store.add_to_counters(&[(eevee, 1), (zorua, 1)], Source::Hotkey).unwrap();
+        store.add_counter(eevee, 5, Source::Button).unwrap();
+
+        // eevee's own change comes first, zorua's press is left alone.
+        assert_eq!(vec![(eevee, 1)], store.undo_batch(eevee).unwrap());
+        assert_eq!(vec![(eevee, 0), (zorua, 0)], store.undo_batch(zorua).unwrap());
+        assert!(store.undo_batch(eevee).unwrap().is_empty());
+        assert_eq!(vec![(eevee, 1), (zorua, 1)], store.redo_batch(eevee).unwrap());
+        assert_eq!(Some(6), store.redo(eevee).unwrap());
+        assert_eq!(1, store.read_counter(zorua).unwrap().counter);
+    }
+
+    #[test]
+    fn test_delete_sprite() {
+        let store = CounterStore::open_in_memory().unwrap();
+        store.save_sprite("pikachu", &sprite(b"GIF89a".to_vec())).unwrap();
+        store.delete_sprite("pikachu").unwrap();
+        assert!(matches!(store.get_sprite("pikachu"), Err(DBError::NotFound(_))));
+        store.delete_sprite("pikachu").unwrap();
+    }
+
+    #[test]
+    fn test_only_images_are_sprites() {
+        let png = b"\x89PNG\r\n\x1a\nIEND";
+        assert_eq!(Some("image/png".to_owned()), image_content_type(Some("image/png"), png));
+        assert_eq!(None, image_content_type(Some("text/html; charset=utf-8"), b"<!DOCTYPE html>"));
+        assert_eq!(Some("image/png".to_owned()), image_content_type(None, png));
+        assert_eq!(None, image_content_type(None, b"<!DOCTYPE html>"));
+    }
+
+    #[test]
+    fn test_chain_follows_count() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.add_counter(id, 30, Source::Button).unwrap();
+        assert_eq!(0, store.set_chain(id, 0).unwrap());
+        store.increment_counter(id, Source::Hotkey).unwrap();
+        store.increment_counter(id, Source::Hotkey).unwrap();
+        let pkmn = store.read_counter(id).unwrap();
+        assert_eq!((32, 2), (pkmn.counter, pkmn.chain));
+
+        store.undo(id).unwrap();
+        assert_eq!(1, store.read_counter(id).unwrap().chain);
+        store.add_counter(id, -5, Source::Button).unwrap();
+        let pkmn = store.read_counter(id).unwrap();
+        assert_eq!((26, 0), (pkmn.counter, pkmn.chain));
+
+        assert!(matches!(store.set_chain(id, -1), Err(DBError::NegativeCount)));
+        assert!(matches!(store.set_chain(id + 1, 3), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_odds_history() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.record_odds(id, 0, 1.0 / 4096.0).unwrap();
+        store.record_odds(id, 100, 3.0 / 4096.0).unwrap();
+        store.record_odds(id, 100, 8.0 / 4096.0).unwrap();
+        store.record_odds(id, 250, 3.0 / 4096.0).unwrap();
+        let history = store.get_odds_history(id).unwrap();
+        assert_eq!(vec![(0, 1.0), (100, 8.0), (250, 3.0)],
+            history.iter().map(|c| (c.start_count, c.odds * 4096.0)).collect::<Vec<_>>());
+
+        // Going back to an earlier count replaces everything after it.
+        store.record_odds(id, 120, 1.0 / 4096.0).unwrap();
+        assert_eq!(vec![0, 100, 120], store.get_odds_history(id).unwrap().iter().map(|c| c.start_count).collect::<Vec<_>>());
+
+        store.delete_counter(id).unwrap();
+        assert!(store.get_odds_history(id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hunt_settings_roundtrip() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        assert_eq!(HuntSettings::default(), store.read_counter(id).unwrap().settings);
+
+        let settings = HuntSettings {
+            game: Some("gen8".to_owned()),
+            methods: vec!["masuda".to_owned(), "charm".to_owned()],
+            search_level: 250,
+        };
+        store.save_hunt_settings(id, &settings).unwrap();
+        assert_eq!(settings, store.read_counter(id).unwrap().settings);
+        assert_eq!(settings, store.finish_hunt(id, HuntStatus::Found, None).unwrap().settings);
+
+        store.save_hunt_settings(id, &HuntSettings::default()).unwrap();
+        assert_eq!(HuntSettings::default(), store.read_counter(id).unwrap().settings);
+        assert!(matches!(store.save_hunt_settings(id + 1, &settings), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!("1970-01-01", format_date(0));
+        assert_eq!("2000-02-29", format_date(951_782_400_000));
+        assert_eq!("2022-08-08", format_date(1_659_916_800_000 + 86_399_999));
+    }
+
+    #[test]
+    fn test_recompute_counter_from_log() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        let id = store.get_row_id("eevee").unwrap();
+        store.add_counter(id, 3, Source::Button).unwrap();
+        store.conn.execute("UPDATE counters SET count = 99 WHERE id = ?1", [id]).unwrap();
+        assert_eq!(3, store.recompute_counter(id).unwrap());
+        assert_eq!(3, store.read_counter(id).unwrap().counter);
+    }
+
+    #[test]
+    fn test_increment_missing_counter_fails() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        assert!(matches!(store.increment_counter(42, Source::Cli), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_concurrent_increments_are_not_lost() {
+        const THREADS: i32 = 8;
+        const PRESSES: i32 = 50;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("count.db");
+        let store = CounterStore::open(&path).unwrap();
+        store.add_new_counter("magikarp").unwrap();
+        let id = store.get_row_id("magikarp").unwrap();
+
+        let handles: Vec<_> = (0..THREADS).map(|_| {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut store = CounterStore::open(&path).unwrap();
+                for _ in 0..PRESSES {
+                    store.increment_counter(id, Source::Hotkey).unwrap();
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(THREADS * PRESSES, store.read_counter(id).unwrap().counter);
+        assert_eq!((THREADS * PRESSES) as usize, store.get_encounters(id).unwrap().len());
+    }
+
+    #[test]
+    fn test_no_counter_loaded() {
+        let mut store = CounterStore::open_in_memory().unwrap();
+        assert!(matches!(store.increment_counter(-1, Source::Cli), Err(DBError::NoActiveCounter)));
+        assert!(matches!(store.read_counter(-1), Err(DBError::NoActiveCounter)));
+    }
+
+    #[test]
+    fn test_unknown_name_is_not_found() {
+        let store = CounterStore::open_in_memory().unwrap();
+        let err = store.get_row_id("missingno").unwrap_err();
+        assert_eq!("Counter with name \"missingno\" does not exist.", err.to_string());
+    }
+
+    #[test]
+    fn test_get_all_counters() {
+        let store = CounterStore::open_in_memory().unwrap();
+        store.add_new_counter("eevee").unwrap();
+        store.add_new_counter("ditto").unwrap();
+        let names: Vec<String> = store.get_all_counters().unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(vec!["eevee".to_owned(), "ditto".to_owned()], names);
+    }
+
+    fn sprite(img: Vec<u8>) -> Sprite {
+        Sprite {
+            img: Bytes::from(img),
+            content_type: Some("image/gif".to_owned()),
+            source_url: Some("https://example.com/ditto.gif".to_owned()),
+            fetched_at: Some(1_660_000_000_000),
+        }
+    }
+
+    #[test]
+    fn test_sprite_roundtrip() {
+        let store = CounterStore::open_in_memory().unwrap();
+        let saved = sprite(b"GIF89a sprite".to_vec());
+        store.save_sprite("ditto", &saved).unwrap();
+        assert_eq!(saved, store.get_sprite("ditto").unwrap());
+    }
+
+    #[test]
+    fn test_sprite_keeps_its_length() {
+        let store = CounterStore::open_in_memory().unwrap();
+        store.save_sprite("ditto", &sprite(vec![7u8; 100_000])).unwrap();
+        assert_eq!(100_000, store.get_sprite("ditto").unwrap().img.len());
+        store.save_sprite("ditto", &sprite(vec![7u8; 10])).unwrap();
+        assert_eq!(10, store.get_sprite("ditto").unwrap().img.len());
+    }
+
+    #[test]
+    fn test_missing_sprite_is_not_found() {
+        let store = CounterStore::open_in_memory().unwrap();
+        assert!(matches!(store.get_sprite("ditto"), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_sprite_too_large() {
+        let store = CounterStore::open_in_memory().unwrap();
+        let img = sprite(vec![1u8; MAX_SPRITE_SIZE + 1]);
+        assert!(matches!(store.save_sprite("wailord", &img), Err(DBError::SpriteTooLarge { .. })));
+    }
+}
@@ -0,0 +1,359 @@
+use rusqlite::{params, Connection, Result, Transaction, TransactionBehavior};
+
+use crate::utils::db_handler::{self, now_millis, sniff_content_type, DBError, Source};
+
+type Migration = fn(&Transaction) -> Result<()>;
+
+/// Size of the ZEROBLOB every sprite used to be written into.
+const LEGACY_SPRITE_SIZE: usize = 22528;
+
+/// Schema changes in the order they are applied. `PRAGMA user_version` stores how many
+/// of them a database has already run, so entries must only ever be appended.
+const MIGRATIONS: &[Migration] = &[
+    create_initial_tables,
+    store_sprites_with_length,
+    add_encounter_log,
+    add_undone_encounters,
+    add_hunt_status,
+    add_hunt_settings,
+    split_gen8_games,
+    split_gen2or3,
+    add_chain,
+    add_search_level,
+    add_odds_history,
+    add_step,
+    add_linked,
+    add_encounter_batches,
+];
+
+fn create_initial_tables(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "create table if not exists counters (
+             name text primary key,
+             count integer not null
+         );
+         create table if not exists sprites (
+             name text primary key,
+             img blob not null
+         );",
+    )
+}
+
+/// Sprites used to be written into a fixed 22528 byte ZEROBLOB. Moves them to a table storing
+/// the image as-is along with where it came from. Every supported image format ends in a
+/// non-zero byte, so trimming the trailing zeros restores images that fit. Larger images were
+/// cut off at the end of the blob and failed writes left only zeros, so those rows are dropped
+/// and the sprites get downloaded again.
+fn store_sprites_with_length(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "create table sprites_new (
+             name text primary key,
+             img blob not null,
+             content_type text,
+             source_url text,
+             fetched_at integer
+         );",
+    )?;
+    {
+        let mut select = tx.prepare("SELECT name, img FROM sprites")?;
+        let mut insert = tx.prepare("INSERT INTO sprites_new (name, img, content_type) VALUES (?1, ?2, ?3)")?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let img: Vec<u8> = row.get(1)?;
+            let len = img.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+            let img = &img[..len];
+            match sniff_content_type(img) {
+                Some(content_type) if len != LEGACY_SPRITE_SIZE => {
+                    insert.execute(params![name, img, content_type])?;
+                }
+                _ => {}
+            }
+        }
+    }
+    tx.execute_batch(
+        "drop table sprites;
+         alter table sprites_new rename to sprites;",
+    )
+}
+
+/// Gives counters a stable `id` (plain rowids may change on VACUUM) and adds the
+/// `encounters` event log. Existing counts are carried over as one imported event
+/// each, so summing a counter's events always gives its count.
+fn add_encounter_log(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "create table counters_new (
+             id integer primary key,
+             name text not null unique,
+             count integer not null
+         );
+         insert into counters_new (id, name, count) select rowid, name, count from counters;
+         drop table counters;
+         alter table counters_new rename to counters;
+         create table encounters (
+             id integer primary key,
+             counter_id integer not null references counters(id),
+             ts integer not null,
+             delta integer not null,
+             source text not null
+         );
+         create index encounters_counter on encounters (counter_id);",
+    )?;
+    tx.execute(
+        "insert into encounters (counter_id, ts, delta, source)
+         select id, ?1, count, ?2 from counters where count != 0",
+        params![now_millis(), Source::Import],
+    )?;
+    Ok(())
+}
+
+/// Undone encounters stay in the log until a new change replaces them, which is what redo replays.
+fn add_undone_encounters(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("alter table encounters add column undone integer not null default 0;")
+}
+
+fn add_hunt_status(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "alter table counters add column status text not null default 'active';
+         alter table counters add column finished_at integer;
+         alter table counters add column final_count integer;
+         alter table counters add column final_odds real;",
+    )
+}
+
+/// `methods` is a comma separated list of method keys.
+fn add_hunt_settings(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "alter table counters add column game text;
+         alter table counters add column methods text not null default '';",
+    )
+}
+
+/// Generation 8 was split into one entry per game. Picks the game from the methods that were in use.
+fn split_gen8_games(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "update counters set game = case
+             when methods like '%dex%' or methods like '%mass%' then 'pla'
+             when methods like '%radar%' or methods like '%underground%' then 'bdsp'
+             else 'swsh'
+         end
+         where game = 'gen8';",
+    )
+}
+
+/// Generations 2 and 3 got separate entries. Their full odds are the same, so Gen 3 is as good a pick as any.
+fn split_gen2or3(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("update counters set game = 'gen3', methods = '' where game = 'gen2or3';")
+}
+
+/// Existing hunts start with a chain as long as their count.
+fn add_chain(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "alter table counters add column chain integer not null default 0;
+         update counters set chain = count;",
+    )
+}
+
+fn add_search_level(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("alter table counters add column search_level integer not null default 0;")
+}
+
+fn add_odds_history(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "create table odds_history (
+             id integer primary key,
+             counter_id integer not null references counters(id),
+             start_count integer not null,
+             odds real not null,
+             unique (counter_id, start_count)
+         );",
+    )
+}
+
+fn add_step(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("alter table counters add column step integer not null default 1;")
+}
+
+fn add_linked(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("alter table counters add column linked integer not null default 0;")
+}
+
+/// Changes made together, e.g. one press on linked counters, share a batch so they are undone together.
+fn add_encounter_batches(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("alter table encounters add column batch integer;")
+}
+
+pub fn schema_version(conn: &Connection) -> Result<usize> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Brings the database up to the latest schema. Refuses databases written by a newer
+/// version of the app, so an old binary never writes to a schema it does not know.
+pub fn migrate(conn: &mut Connection) -> db_handler::Result<()> {
+    apply_migrations(conn, MIGRATIONS)
+}
+
+/// Runs every migration the database has not seen yet inside a single transaction,
+/// so a failing step leaves the file exactly as it was.
+fn apply_migrations(conn: &mut Connection, migrations: &[Migration]) -> db_handler::Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = schema_version(&tx)?;
+    if version > migrations.len() {
+        return Err(DBError::SchemaTooNew { found: version, supported: migrations.len() });
+    }
+    for migration in &migrations[version..] {
+        migration(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", migrations.len() as i64)?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{params, Connection, Transaction};
+
+    use super::{apply_migrations, create_initial_tables, migrate, schema_version, Migration, MIGRATIONS};
+    use crate::utils::db_handler::DBError;
+
+    /// The schema written by versions of the app from before migrations existed.
+    const LEGACY_SCHEMA: &str =
+        "create table if not exists counters (
+             name text primary key,
+             count integer not null
+         );
+         create table if not exists sprites (
+             name text primary key,
+             img blob not null
+         );";
+
+    fn legacy_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_SCHEMA).unwrap();
+        conn.execute("INSERT INTO counters (name, count) VALUES (?1, ?2)", params!["pikachu", 312]).unwrap();
+        conn.execute("INSERT INTO counters (name, count) VALUES (?1, ?2)", params!["eevee", 0]).unwrap();
+        conn.execute("INSERT INTO sprites (name, img) VALUES (?1, ?2)", params!["pikachu", padded(PNG)]).unwrap();
+        conn
+    }
+
+    /// The smallest file the sprite migration recognizes as a PNG.
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nIEND\xaeB`\x82";
+
+    fn padded(img: &[u8]) -> Vec<u8> {
+        let mut padded = img.to_vec();
+        padded.resize(22528, 0);
+        padded
+    }
+
+    fn failing_migration(tx: &Transaction) -> rusqlite::Result<()> {
+        tx.execute("INSERT INTO counters (name, count) VALUES ('ditto', 1)", [])?;
+        tx.execute("this is not sql", []).map(|_| ())
+    }
+
+    #[test]
+    fn test_fresh_db_gets_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(MIGRATIONS.len(), schema_version(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_legacy_db_upgrades_losslessly() {
+        let mut conn = legacy_db();
+        assert_eq!(0, schema_version(&conn).unwrap());
+        migrate(&mut conn).unwrap();
+        assert_eq!(MIGRATIONS.len(), schema_version(&conn).unwrap());
+
+        let count: i32 = conn.query_row("SELECT count FROM counters WHERE name = 'pikachu'", [], |r| r.get(0)).unwrap();
+        assert_eq!(312, count);
+        let count: i32 = conn.query_row("SELECT count FROM counters WHERE name = 'eevee'", [], |r| r.get(0)).unwrap();
+        assert_eq!(0, count);
+        let img: Vec<u8> = conn.query_row("SELECT img FROM sprites WHERE name = 'pikachu'", [], |r| r.get(0)).unwrap();
+        assert_eq!(PNG.to_vec(), img);
+    }
+
+    #[test]
+    fn test_legacy_counts_become_encounters() {
+        let mut conn = legacy_db();
+        let rowid: i32 = conn.query_row("SELECT rowid FROM counters WHERE name = 'pikachu'", [], |r| r.get(0)).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let id: i32 = conn.query_row("SELECT id FROM counters WHERE name = 'pikachu'", [], |r| r.get(0)).unwrap();
+        assert_eq!(rowid, id);
+        let (total, source): (i32, String) = conn.query_row(
+            "SELECT sum(delta), max(source) FROM encounters WHERE counter_id = ?1", params![id], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+        assert_eq!(312, total);
+        assert_eq!("import", source);
+        let events: i32 = conn.query_row("SELECT count(*) FROM encounters", [], |r| r.get(0)).unwrap();
+        assert_eq!(1, events);
+    }
+
+    #[test]
+    fn test_padded_sprites_are_trimmed() {
+        let mut conn = legacy_db();
+        conn.execute("INSERT INTO sprites (name, img) VALUES (?1, ?2)", params!["eevee", padded(PNG)]).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let (img, content_type): (Vec<u8>, Option<String>) = conn.query_row(
+            "SELECT img, content_type FROM sprites WHERE name = 'eevee'", [], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+        assert_eq!(PNG.to_vec(), img);
+        assert_eq!(Some("image/png".to_owned()), content_type);
+    }
+
+    #[test]
+    fn test_broken_sprites_are_dropped() {
+        let mut conn = legacy_db();
+        let mut cut_off = PNG.to_vec();
+        cut_off.resize(22528, 1);
+        conn.execute("INSERT INTO sprites (name, img) VALUES (?1, ?2)", params!["eevee", cut_off]).unwrap();
+        conn.execute("INSERT INTO sprites (name, img) VALUES (?1, ?2)", params!["ditto", padded(&[])]).unwrap();
+        conn.execute("INSERT INTO sprites (name, img) VALUES (?1, ?2)", params!["mew", padded(b"<html>")]).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let names = conn.prepare("SELECT name FROM sprites").unwrap()
+            .query_map([], |r| r.get(0)).unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>().unwrap();
+        assert_eq!(vec!["pikachu".to_owned()], names);
+    }
+
+    #[test]
+    fn test_migrate_twice_is_noop() {
+        let mut conn = legacy_db();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(MIGRATIONS.len(), schema_version(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_gen8_settings_pick_a_game() {
+        let mut conn = legacy_db();
+        apply_migrations(&mut conn, &MIGRATIONS[..6]).unwrap();
+        conn.execute_batch(
+            "update counters set game = 'gen8', methods = 'charm,dex10' where name = 'pikachu';
+             update counters set game = 'gen8', methods = 'masuda' where name = 'eevee';").unwrap();
+        migrate(&mut conn).unwrap();
+
+        let game: String = conn.query_row("SELECT game FROM counters WHERE name = 'pikachu'", [], |r| r.get(0)).unwrap();
+        assert_eq!("pla", game);
+        let game: String = conn.query_row("SELECT game FROM counters WHERE name = 'eevee'", [], |r| r.get(0)).unwrap();
+        assert_eq!("swsh", game);
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let mut conn = legacy_db();
+        conn.pragma_update(None, "user_version", (MIGRATIONS.len() + 1) as i64).unwrap();
+        let res = migrate(&mut conn);
+        assert!(matches!(res, Err(DBError::SchemaTooNew { found, supported }) if found == MIGRATIONS.len() + 1 && supported == MIGRATIONS.len()));
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let mut conn = legacy_db();
+        let migrations: &[Migration] = &[create_initial_tables, failing_migration];
+        assert!(apply_migrations(&mut conn, migrations).is_err());
+        assert_eq!(0, schema_version(&conn).unwrap());
+        let ditto: i32 = conn.query_row("SELECT count(*) FROM counters WHERE name = 'ditto'", [], |r| r.get(0)).unwrap();
+        assert_eq!(0, ditto);
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::{HashMap, VecDeque};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use inputbot::{BlockInput, KeybdKey};
+use serde::{Deserialize, Serialize};
+
+/// Keys that can be bound, by the name used in `config.toml`.
+/// Keys without an egui counterpart can only be pressed through the global listener.
+const KEYS: &[(&str, Option<egui::Key>, KeybdKey)] = &[
+    ("Home", Some(egui::Key::Home), KeybdKey::HomeKey),
+    ("Insert", Some(egui::Key::Insert), KeybdKey::InsertKey),
+    ("Delete", Some(egui::Key::Delete), KeybdKey::DeleteKey),
+    ("Backspace", Some(egui::Key::Backspace), KeybdKey::BackspaceKey),
+    ("Enter", Some(egui::Key::Enter), KeybdKey::EnterKey),
+    ("Space", Some(egui::Key::Space), KeybdKey::SpaceKey),
+    ("Tab", Some(egui::Key::Tab), KeybdKey::TabKey),
+    ("Up", Some(egui::Key::ArrowUp), KeybdKey::UpKey),
+    ("Down", Some(egui::Key::ArrowDown), KeybdKey::DownKey),
+    ("Left", Some(egui::Key::ArrowLeft), KeybdKey::LeftKey),
+    ("Right", Some(egui::Key::ArrowRight), KeybdKey::RightKey),
+    ("0", Some(egui::Key::Num0), KeybdKey::Numrow0Key),
+    ("1", Some(egui::Key::Num1), KeybdKey::Numrow1Key),
+    ("2", Some(egui::Key::Num2), KeybdKey::Numrow2Key),
+    ("3", Some(egui::Key::Num3), KeybdKey::Numrow3Key),
+    ("4", Some(egui::Key::Num4), KeybdKey::Numrow4Key),
+    ("5", Some(egui::Key::Num5), KeybdKey::Numrow5Key),
+    ("6", Some(egui::Key::Num6), KeybdKey::Numrow6Key),
+    ("7", Some(egui::Key::Num7), KeybdKey::Numrow7Key),
+    ("8", Some(egui::Key::Num8), KeybdKey::Numrow8Key),
+    ("9", Some(egui::Key::Num9), KeybdKey::Numrow9Key),
+    ("A", Some(egui::Key::A), KeybdKey::AKey),
+    ("B", Some(egui::Key::B), KeybdKey::BKey),
+    ("C", Some(egui::Key::C), KeybdKey::CKey),
+    ("D", Some(egui::Key::D), KeybdKey::DKey),
+    ("E", Some(egui::Key::E), KeybdKey::EKey),
+    ("F", Some(egui::Key::F), KeybdKey::FKey),
+    ("G", Some(egui::Key::G), KeybdKey::GKey),
+    ("H", Some(egui::Key::H), KeybdKey::HKey),
+    ("I", Some(egui::Key::I), KeybdKey::IKey),
+    ("J", Some(egui::Key::J), KeybdKey::JKey),
+    ("K", Some(egui::Key::K), KeybdKey::KKey),
+    ("L", Some(egui::Key::L), KeybdKey::LKey),
+    ("M", Some(egui::Key::M), KeybdKey::MKey),
+    ("N", Some(egui::Key::N), KeybdKey::NKey),
+    ("O", Some(egui::Key::O), KeybdKey::OKey),
+    ("P", Some(egui::Key::P), KeybdKey::PKey),
+    ("Q", Some(egui::Key::Q), KeybdKey::QKey),
+    ("R", Some(egui::Key::R), KeybdKey::RKey),
+    ("S", Some(egui::Key::S), KeybdKey::SKey),
+    ("T", Some(egui::Key::T), KeybdKey::TKey),
+    ("U", Some(egui::Key::U), KeybdKey::UKey),
+    ("V", Some(egui::Key::V), KeybdKey::VKey),
+    ("W", Some(egui::Key::W), KeybdKey::WKey),
+    ("X", Some(egui::Key::X), KeybdKey::XKey),
+    ("Y", Some(egui::Key::Y), KeybdKey::YKey),
+    ("Z", Some(egui::Key::Z), KeybdKey::ZKey),
+    ("F1", Some(egui::Key::F1), KeybdKey::F1Key),
+    ("F2", Some(egui::Key::F2), KeybdKey::F2Key),
+    ("F3", Some(egui::Key::F3), KeybdKey::F3Key),
+    ("F4", Some(egui::Key::F4), KeybdKey::F4Key),
+    ("F5", Some(egui::Key::F5), KeybdKey::F5Key),
+    ("F6", Some(egui::Key::F6), KeybdKey::F6Key),
+    ("F7", Some(egui::Key::F7), KeybdKey::F7Key),
+    ("F8", Some(egui::Key::F8), KeybdKey::F8Key),
+    ("F9", Some(egui::Key::F9), KeybdKey::F9Key),
+    ("F10", Some(egui::Key::F10), KeybdKey::F10Key),
+    ("F11", Some(egui::Key::F11), KeybdKey::F11Key),
+    ("F12", Some(egui::Key::F12), KeybdKey::F12Key),
+    ("F13", Some(egui::Key::F13), KeybdKey::F13Key),
+    ("F14", Some(egui::Key::F14), KeybdKey::F14Key),
+    ("F15", Some(egui::Key::F15), KeybdKey::F15Key),
+    ("F16", Some(egui::Key::F16), KeybdKey::F16Key),
+    ("F17", Some(egui::Key::F17), KeybdKey::F17Key),
+    ("F18", Some(egui::Key::F18), KeybdKey::F18Key),
+    ("F19", Some(egui::Key::F19), KeybdKey::F19Key),
+    ("F20", Some(egui::Key::F20), KeybdKey::F20Key),
+    ("Numpad0", None, KeybdKey::Numpad0Key),
+    ("Numpad1", None, KeybdKey::Numpad1Key),
+    ("Numpad2", None, KeybdKey::Numpad2Key),
+    ("Numpad3", None, KeybdKey::Numpad3Key),
+    ("Numpad4", None, KeybdKey::Numpad4Key),
+    ("Numpad5", None, KeybdKey::Numpad5Key),
+    ("Numpad6", None, KeybdKey::Numpad6Key),
+    ("Numpad7", None, KeybdKey::Numpad7Key),
+    ("Numpad8", None, KeybdKey::Numpad8Key),
+    ("Numpad9", None, KeybdKey::Numpad9Key),
+    ("ScrollLock", None, KeybdKey::ScrollLockKey),
+];
+
+/// Long enough to swallow switch bounce, shorter than any deliberate double press.
+pub const DEFAULT_DEBOUNCE_MS: u64 = 50;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Whether the thread dispatching global key events is running. It stops by itself once no key is bound.
+static LISTENING: AtomicBool = AtomicBool::new(false);
+/// While set, global presses are let through to other windows and not reported.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Increment,
+    Decrement,
+    NextCounter,
+    PreviousCounter,
+    /// Increments the counter named in the binding, whichever one is selected.
+    IncrementCounter,
+    Undo,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::Increment,
+        Action::Decrement,
+        Action::NextCounter,
+        Action::PreviousCounter,
+        Action::IncrementCounter,
+        Action::Undo,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Increment => "Increment",
+            Action::Decrement => "Decrement",
+            Action::NextCounter => "Next counter",
+            Action::PreviousCounter => "Previous counter",
+            Action::IncrementCounter => "Increment counter",
+            Action::Undo => "Undo",
+        }
+    }
+}
+
+/// A key and what pressing it does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub key: String,
+    pub action: Action,
+    /// The counter name used by [`Action::IncrementCounter`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<String>,
+    /// Counts this many instead of the counter's step, or instead of one when decrementing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i32>,
+    /// Only fires while Shift is held, and the plain key only while it is not.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub shift: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ctrl: bool,
+    /// Presses this soon after the previous one are ignored, including key repeat.
+    #[serde(default = "default_debounce", skip_serializing_if = "is_default_debounce")]
+    pub debounce_ms: u64,
+    /// Presses past this many per second are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_second: Option<u32>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn default_debounce() -> u64 {
+    DEFAULT_DEBOUNCE_MS
+}
+
+fn is_default_debounce(value: &u64) -> bool {
+    *value == DEFAULT_DEBOUNCE_MS
+}
+
+/// Whether a key press was counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Accepted,
+    /// Came within the debounce window of the previous press.
+    Bounced,
+    /// Went over the binding's maximum rate.
+    TooFast,
+}
+
+impl Press {
+    pub fn label(self) -> &'static str {
+        match self {
+            Press::Accepted => "accepted",
+            Press::Bounced => "bounced",
+            Press::TooFast => "too fast",
+        }
+    }
+}
+
+/// Applies a binding's debounce window and rate limit to its presses.
+#[derive(Debug, Default)]
+pub struct PressFilter {
+    last: Option<Instant>,
+    /// Accepted presses within the last second.
+    recent: VecDeque<Instant>,
+}
+
+impl PressFilter {
+    pub fn check(&mut self, binding: &Binding, now: Instant) -> Press {
+        let previous = self.last.replace(now);
+        if previous.is_some_and(|t| now.duration_since(t) < Duration::from_millis(binding.debounce_ms)) {
+            return Press::Bounced;
+        }
+        while self.recent.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+            self.recent.pop_front();
+        }
+        if binding.max_per_second.is_some_and(|max| self.recent.len() >= max as usize) {
+            return Press::TooFast;
+        }
+        self.recent.push_back(now);
+        Press::Accepted
+    }
+}
+
+impl Binding {
+    pub fn new(key: &str, action: Action) -> Self {
+        Binding {
+            key: key.to_owned(),
+            action,
+            counter: None,
+            amount: None,
+            shift: false,
+            ctrl: false,
+            debounce_ms: DEFAULT_DEBOUNCE_MS,
+            max_per_second: None,
+        }
+    }
+
+    /// The key with its modifiers, e.g. "Shift+Home".
+    pub fn key_label(&self) -> String {
+        let mut label = String::new();
+        if self.ctrl { label.push_str("Ctrl+"); }
+        if self.shift { label.push_str("Shift+"); }
+        label.push_str(&self.key);
+        label
+    }
+
+    pub fn matches_modifiers(&self, shift: bool, ctrl: bool) -> bool {
+        self.shift == shift && self.ctrl == ctrl
+    }
+}
+
+/// Home increments in the window, as it always has.
+pub fn default_bindings() -> Vec<Binding> {
+    vec![Binding::new("Home", Action::Increment)]
+}
+
+/// The command-line only ever grabbed Scroll Lock, which nothing else uses.
+pub fn default_repl_bindings() -> Vec<Binding> {
+    vec![Binding::new("ScrollLock", Action::Increment)]
+}
+
+/// Keys that type text. Blocking them globally would break typing in every other program.
+pub fn is_typing_key(name: &str) -> bool {
+    name.len() == 1 || ["Backspace", "Enter", "Space", "Tab"].contains(&name)
+}
+
+/// Whether a global binding swallows the key. inputbot only reports presses
+/// of unblocked keys on Linux, so nothing is blocked there.
+fn blocks(key: KeybdKey) -> bool {
+    !cfg!(target_os = "linux") && KEYS.iter().any(|(name, _, k)| *k == key && !is_typing_key(name))
+}
+
+fn find_key(name: &str) -> Option<&'static (&'static str, Option<egui::Key>, KeybdKey)> {
+    KEYS.iter().find(|(n, _, _)| n.eq_ignore_ascii_case(name))
+}
+
+pub fn egui_key(name: &str) -> Option<egui::Key> {
+    find_key(name).and_then(|(_, key, _)| *key)
+}
+
+pub fn keybd_key(name: &str) -> Option<KeybdKey> {
+    find_key(name).map(|(_, _, key)| *key)
+}
+
+/// Whether presses of the key reach the window while it has focus, on top of the global listener.
+pub fn reaches_window(name: &str) -> bool {
+    egui_key(name).is_some() && keybd_key(name).is_some_and(|k| !blocks(k))
+}
+
+/// The config name of a key pressed inside the window, if it can be bound.
+pub fn key_name(key: egui::Key) -> Option<&'static str> {
+    KEYS.iter().find(|(_, k, _)| *k == Some(key)).map(|(n, _, _)| *n)
+}
+
+/// Lists the keys bound to `action`, e.g. "Home, ScrollLock".
+pub fn describe(bindings: &[Binding], action: Action) -> String {
+    let keys = bindings.iter()
+        .filter(|b| b.action == action)
+        .map(|b| b.key_label())
+        .collect::<Vec<_>>();
+    if keys.is_empty() { "none".to_owned() } else { keys.join(", ") }
+}
+
+/// Binds every key system-wide so it is caught while the window is in the background.
+/// Keys are kept from other programs where possible, except typing keys.
+/// Bindings sharing a key and modifiers all fire; keys that cannot be bound are reported and skipped.
+/// Every press is passed on, together with whether its binding's filter let it through.
+/// Keys bound before are replaced in place, so the listener never sees an empty set of keys.
+pub fn bind_global<F>(bindings: &[Binding], on_press: F)
+where F: Fn(&Binding, Press) + Send + Sync + 'static {
+    let on_press = Arc::new(on_press);
+    let mut by_key: HashMap<KeybdKey, Vec<(Binding, Mutex<PressFilter>)>> = HashMap::new();
+    for binding in bindings {
+        match keybd_key(&binding.key) {
+            Some(key) => by_key.entry(key).or_default().push((binding.clone(), Mutex::default())),
+            None => println!("Ignoring binding for unknown key {:?}", binding.key),
+        }
+    }
+    for (key, bound) in by_key {
+        let on_press = Arc::clone(&on_press);
+        let handler = move || {
+            if PAUSED.load(Ordering::SeqCst) {
+                return BlockInput::DontBlock;
+            }
+            let shift = KeybdKey::LShiftKey.is_pressed() || KeybdKey::RShiftKey.is_pressed();
+            let ctrl = KeybdKey::LControlKey.is_pressed() || KeybdKey::RControlKey.is_pressed();
+            let now = Instant::now();
+            for (binding, filter) in bound.iter().filter(|(b, _)| b.matches_modifiers(shift, ctrl)) {
+                let press = filter.lock().map_or(Press::Accepted, |mut f| f.check(binding, now));
+                on_press(binding, press);
+            }
+            BlockInput::Block
+        };
+        if blocks(key) {
+            key.blockable_bind(handler);
+        } else {
+            key.bind(move || { handler(); });
+        }
+    }
+}
+
+/// Unbinds the keys of `old` that are not used by `new`.
+pub fn unbind_unused(old: &[Binding], new: &[Binding]) {
+    let used = new.iter().filter_map(|b| keybd_key(&b.key)).collect::<Vec<_>>();
+    for key in old.iter().filter_map(|b| keybd_key(&b.key)).filter(|k| !used.contains(k)) {
+        key.unbind();
+    }
+}
+
+/// Stops reporting global presses without unbinding them, e.g. while keys are being rebound.
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+}
+
+/// Starts the thread that dispatches global key events, unless it is already running.
+pub fn listen() {
+    if LISTENING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(|| {
+        if panic::catch_unwind(inputbot::handle_input_events).is_err() {
+            println!("Global hotkeys are unavailable.");
+        }
+        LISTENING.store(false, Ordering::SeqCst);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use eframe::egui;
+    use inputbot::KeybdKey;
+
+    use super::{default_bindings, default_repl_bindings, describe, egui_key, is_typing_key, key_name, keybd_key, reaches_window, Action, Binding, Press, PressFilter, KEYS};
+
+    #[test]
+    fn test_key_names_round_trip() {
+        for (name, key, _) in KEYS {
+            if let Some(key) = key {
+                assert_eq!(Some(*name), key_name(*key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_lookup_ignores_case() {
+        assert_eq!(Some(egui::Key::Home), egui_key("home"));
+        assert_eq!(Some(KeybdKey::ScrollLockKey), keybd_key("scrolllock"));
+        assert_eq!(None, egui_key("ScrollLock"));
+        assert_eq!(None, keybd_key("Hyper"));
+    }
+
+    #[test]
+    fn test_describe_lists_keys() {
+        assert_eq!("Home", describe(&default_bindings(), Action::Increment));
+        assert_eq!("ScrollLock", describe(&default_repl_bindings(), Action::Increment));
+        assert_eq!("none", describe(&default_bindings(), Action::Undo));
+    }
+
+    #[test]
+    fn test_typing_keys() {
+        for name in ["A", "7", "Enter", "Space", "Tab", "Backspace"] {
+            assert!(is_typing_key(name), "{}", name);
+        }
+        for name in ["Home", "F1", "Numpad1", "ScrollLock", "Up"] {
+            assert!(!is_typing_key(name), "{}", name);
+        }
+        assert!(reaches_window("A"));
+        assert!(!reaches_window("ScrollLock"));
+    }
+
+    #[test]
+    fn test_parse_bindings() {
+        #[derive(serde::Deserialize)]
+        struct Bindings {
+            bindings: Vec<Binding>,
+        }
+        let text = r#"
+            [[bindings]]
+            key = "F2"
+            action = "increment_counter"
+            counter = "Ralts"
+
+            [[bindings]]
+            key = "Z"
+            action = "undo"
+
+            [[bindings]]
+            key = "Home"
+            shift = true
+            action = "increment"
+            amount = 5
+            debounce_ms = 0
+            max_per_second = 4
+        "#;
+        let parsed: Bindings = toml::from_str(text).unwrap();
+        assert_eq!(vec![
+            Binding { counter: Some("Ralts".to_owned()), ..Binding::new("F2", Action::IncrementCounter) },
+            Binding::new("Z", Action::Undo),
+            Binding {
+                amount: Some(5),
+                shift: true,
+                debounce_ms: 0,
+                max_per_second: Some(4),
+                ..Binding::new("Home", Action::Increment)
+            },
+        ], parsed.bindings);
+    }
+
+    #[test]
+    fn test_modifiers_must_match() {
+        let plain = Binding::new("Home", Action::Increment);
+        let shifted = Binding { shift: true, ..plain.clone() };
+        assert!(plain.matches_modifiers(false, false));
+        assert!(!plain.matches_modifiers(true, false));
+        assert!(shifted.matches_modifiers(true, false));
+        assert!(!shifted.matches_modifiers(true, true));
+        assert_eq!("Shift+Home", shifted.key_label());
+        assert_eq!("Home, Shift+Home", describe(&[plain, shifted], Action::Increment));
+    }
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_debounce_swallows_bursts() {
+        let binding = Binding { debounce_ms: 50, ..Binding::new("Home", Action::Increment) };
+        let mut filter = PressFilter::default();
+        let start = Instant::now();
+        assert_eq!(Press::Accepted, filter.check(&binding, start));
+        assert_eq!(Press::Bounced, filter.check(&binding, at(start, 20)));
+        // Measured from the last press, so a held key keeps bouncing.
+        assert_eq!(Press::Bounced, filter.check(&binding, at(start, 60)));
+        assert_eq!(Press::Accepted, filter.check(&binding, at(start, 200)));
+    }
+
+    #[test]
+    fn test_rate_limit_per_second() {
+        let binding = Binding { debounce_ms: 0, max_per_second: Some(2), ..Binding::new("Home", Action::Increment) };
+        let mut filter = PressFilter::default();
+        let start = Instant::now();
+        assert_eq!(Press::Accepted, filter.check(&binding, start));
+        assert_eq!(Press::Accepted, filter.check(&binding, at(start, 100)));
+        assert_eq!(Press::TooFast, filter.check(&binding, at(start, 200)));
+        assert_eq!(Press::Accepted, filter.check(&binding, at(start, 1000)));
+        assert_eq!(Press::TooFast, filter.check(&binding, at(start, 1050)));
+        assert_eq!(Press::Accepted, filter.check(&binding, at(start, 1100)));
+    }
+}
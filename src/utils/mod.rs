@@ -1,3 +1,8 @@
+pub mod arg_handler;
 pub mod cli_handler;
+pub mod config_handler;
 pub mod db_handler;
-pub mod prob_handler;
\ No newline at end of file
+pub mod db_migrations;
+pub mod hotkey_handler;
+pub mod odds_handler;
+pub mod prob_handler;
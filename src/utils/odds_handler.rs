@@ -0,0 +1,562 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::utils::config_handler::odds_rules_path;
+use crate::utils::db_handler::Pokemon;
+use crate::utils::prob_handler::probability_within_n_tries;
+
+/// The rules shipped with the app, see `rules/odds.toml` for the format.
+const DEFAULT_RULES: &str = include_str!("../../rules/odds.toml");
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OddsRules {
+    #[serde(default, rename = "game")]
+    pub games: Vec<GameRules>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GameRules {
+    pub id: String,
+    pub name: String,
+    /// Full odds are `1 / base_odds`.
+    pub base_odds: f64,
+    #[serde(default)]
+    pub formula: Formula,
+    #[serde(default, rename = "method")]
+    pub methods: Vec<MethodRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MethodRule {
+    pub id: String,
+    pub name: String,
+    /// Extra rerolls granted while the method is in use.
+    #[serde(default)]
+    pub bonus: f64,
+    /// `(chain length, bonus)` steps, sorted by chain length. When present,
+    /// the last step the chain has reached replaces `bonus`.
+    #[serde(default)]
+    pub chain: Vec<(u32, f64)>,
+    /// `(every, bonus)` extra rolls when the chain length is a multiple of `every`.
+    /// The largest matching multiple applies, e.g. DexNav's 50th and 100th encounters.
+    #[serde(default)]
+    pub chain_every: Vec<(u32, f64)>,
+    pub search_level: Option<SearchLevelRule>,
+    /// Number of Pokémon in one outbreak, for methods that are outbreaks.
+    pub spawns: Option<u32>,
+    /// Methods that cannot be used together with this one.
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    /// Methods sharing a group are levels of one bonus, e.g. Sparkling Power 1 to 3.
+    pub group: Option<String>,
+    /// Replaces the game's base odds, e.g. Gen 2 breeding with a shiny parent.
+    pub base_odds: Option<f64>,
+}
+
+/// A check made on every encounter whose chance grows with the search level, like the DexNav's.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SearchLevelRule {
+    /// Extra rolls when the check passes.
+    pub rolls: f64,
+    /// `(search level, chance in 1/10000 added per level)` steps, sorted by search level.
+    pub steps: Vec<(u32, f64)>,
+}
+
+/// How far along a hunt is, for methods that depend on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub chain: u32,
+    pub search_level: u32,
+}
+
+/// How the rerolls of a game turn into odds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Formula {
+    /// `rolls / base_odds`, the approximation most guides use.
+    #[default]
+    Additive,
+    /// `1 - (1 - 1 / base_odds)^rolls`, the chance that any of the rolls hits.
+    Rolls,
+}
+
+impl OddsRules {
+    pub fn game(&self, id: &str) -> Option<&GameRules> {
+        self.games.iter().find(|g| g.id == id)
+    }
+
+    /// The odds per encounter of a hunt's saved game, methods and chain,
+    /// or `None` if no known game was picked.
+    pub fn hunt_probability(&self, pkmn: &Pokemon) -> Option<f64> {
+        let settings = &pkmn.settings;
+        let game = settings.game.as_deref().and_then(|id| self.game(id))?;
+        let progress = Progress { chain: pkmn.chain.max(0) as u32, search_level: settings.search_level };
+        Some(game.probability(&settings.methods, progress)).filter(|p| *p > 0.0)
+    }
+
+    /// Replaces games with the same id and appends new ones.
+    fn merge(&mut self, other: OddsRules) {
+        for game in other.games {
+            match self.games.iter_mut().find(|g| g.id == game.id) {
+                Some(existing) => *existing = game,
+                None => self.games.push(game),
+            }
+        }
+    }
+}
+
+impl SearchLevelRule {
+    /// Chance that the check passes at the given search level.
+    pub fn chance(&self, level: u32) -> f64 {
+        let mut permyriad = 0.0;
+        for (i, (start, per_level)) in self.steps.iter().enumerate() {
+            if level <= *start {
+                break;
+            }
+            let end = self.steps.get(i + 1).map_or(level, |(next, _)| (*next).min(level));
+            permyriad += (end - start) as f64 * per_level;
+        }
+        (permyriad / 10000.0).min(1.0)
+    }
+}
+
+impl MethodRule {
+    pub fn bonus_at(&self, chain: u32) -> f64 {
+        let every = self.chain_every.iter()
+            .filter(|(every, _)| chain > 0 && chain.is_multiple_of(*every))
+            .map(|(_, bonus)| *bonus)
+            .fold(0.0, f64::max);
+        if self.chain.is_empty() {
+            return self.bonus + every;
+        }
+        self.chain.iter()
+            .take_while(|(length, _)| *length <= chain)
+            .last()
+            .map_or(0.0, |(_, bonus)| *bonus) + every
+    }
+}
+
+impl GameRules {
+    pub fn method(&self, id: &str) -> Option<&MethodRule> {
+        self.methods.iter().find(|m| m.id == id)
+    }
+
+    /// Methods without a group, shown as checkboxes.
+    pub fn single_methods(&self) -> impl Iterator<Item = &MethodRule> {
+        self.methods.iter().filter(|m| m.group.is_none())
+    }
+
+    /// Group names in the order they first appear.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = vec![];
+        for group in self.methods.iter().filter_map(|m| m.group.as_deref()) {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    pub fn group_methods<'a>(&'a self, group: &'a str) -> impl Iterator<Item = &'a MethodRule> {
+        self.methods.iter().filter(move |m| m.group.as_deref() == Some(group))
+    }
+
+    /// Exclusions only need to be listed on one of the two methods.
+    pub fn conflicts(&self, a: &str, b: &str) -> bool {
+        let excludes = |x: &str, y: &str| self.method(x).is_some_and(|m| m.excludes.iter().any(|e| e == y));
+        let same_group = match (self.method(a), self.method(b)) {
+            (Some(x), Some(y)) => x.group.is_some() && x.group == y.group,
+            _ => false,
+        };
+        excludes(a, b) || excludes(b, a) || same_group
+    }
+
+    /// Turns `method` on or off, dropping enabled methods it cannot be combined with.
+    pub fn toggle(&self, enabled: &mut Vec<String>, method: &str, on: bool) {
+        enabled.retain(|m| m != method && !(on && self.conflicts(m, method)));
+        if on {
+            enabled.push(method.to_owned());
+        }
+    }
+
+    /// Whether any of the given methods ramps up with the chain.
+    pub fn uses_chain(&self, enabled: &[String]) -> bool {
+        enabled.iter().filter_map(|id| self.method(id)).any(|m| !m.chain.is_empty() || !m.chain_every.is_empty())
+    }
+
+    fn search_level(&self, enabled: &[String]) -> Option<&SearchLevelRule> {
+        enabled.iter().filter_map(|id| self.method(id)).find_map(|m| m.search_level.as_ref())
+    }
+
+    pub fn uses_search_level(&self, enabled: &[String]) -> bool {
+        self.search_level(enabled).is_some()
+    }
+
+    /// Pokémon per outbreak if one of the methods is an outbreak.
+    pub fn outbreak_spawns(&self, enabled: &[String]) -> Option<u32> {
+        enabled.iter().filter_map(|id| self.method(id)).find_map(|m| m.spawns)
+    }
+
+    /// Chance that at least one Pokémon of a whole outbreak is shiny.
+    pub fn outbreak_probability(&self, enabled: &[String], progress: Progress) -> Option<f64> {
+        let spawns = self.outbreak_spawns(enabled)?;
+        Some(probability_within_n_tries(spawns as i32, self.probability(enabled, progress)))
+    }
+
+    /// Number of rolls per encounter with the given methods at a chain length. Unknown ids are ignored.
+    pub fn rolls(&self, enabled: &[String], chain: u32) -> f64 {
+        1.0 + enabled.iter()
+            .filter_map(|id| self.method(id))
+            .map(|m| m.bonus_at(chain))
+            .sum::<f64>()
+    }
+
+    pub fn base_probability(&self) -> f64 {
+        1.0 / self.base_odds
+    }
+
+    /// The base odds after methods that replace them, picking the best if several do.
+    pub fn effective_base_odds(&self, enabled: &[String]) -> f64 {
+        enabled.iter()
+            .filter_map(|id| self.method(id))
+            .filter_map(|m| m.base_odds)
+            .fold(self.base_odds, f64::min)
+    }
+
+    /// Chance of a shiny per encounter with the given methods.
+    pub fn probability(&self, enabled: &[String], progress: Progress) -> f64 {
+        let rolls = self.rolls(enabled, progress.chain);
+        let base_odds = self.effective_base_odds(enabled);
+        let with_rolls = |rolls: f64| match self.formula {
+            Formula::Additive => rolls / base_odds,
+            Formula::Rolls => 1.0 - (1.0 - 1.0 / base_odds).powf(rolls),
+        };
+        match self.search_level(enabled) {
+            Some(search) => {
+                let pass = search.chance(progress.search_level);
+                pass * with_rolls(rolls + search.rolls) + (1.0 - pass) * with_rolls(rolls)
+            }
+            None => with_rolls(rolls),
+        }
+    }
+}
+
+pub fn parse_rules(text: &str) -> Result<OddsRules, String> {
+    let rules: OddsRules = toml::from_str(text).map_err(|e| e.to_string())?;
+    for game in &rules.games {
+        if game.base_odds.is_nan() || game.base_odds < 1.0 {
+            return Err(format!("game {:?} needs base_odds of at least 1", game.id));
+        }
+        for method in &game.methods {
+            if method.base_odds.is_some_and(|o| o.is_nan() || o < 1.0) {
+                return Err(format!("method {:?} of {:?} needs base_odds of at least 1", method.id, game.id));
+            }
+            if method.chain.windows(2).any(|w| w[0].0 >= w[1].0) {
+                return Err(format!("chain steps of method {:?} of {:?} must be sorted", method.id, game.id));
+            }
+            if method.search_level.as_ref().is_some_and(|s| s.steps.windows(2).any(|w| w[0].0 >= w[1].0)) {
+                return Err(format!("search level steps of method {:?} of {:?} must be sorted", method.id, game.id));
+            }
+            if let Some(unknown) = method.excludes.iter().find(|e| game.method(e).is_none()) {
+                return Err(format!("method {:?} of {:?} excludes unknown method {:?}", method.id, game.id, unknown));
+            }
+        }
+    }
+    Ok(rules)
+}
+
+pub fn default_rules() -> OddsRules {
+    parse_rules(DEFAULT_RULES).expect("embedded odds rules are valid")
+}
+
+/// Applies the override file at `path` to the built-in rules, ignoring it if it is missing or invalid.
+pub fn load_rules_from(path: &Path) -> OddsRules {
+    let mut rules = default_rules();
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(_) => return rules,
+    };
+    match parse_rules(&text) {
+        Ok(overrides) => rules.merge(overrides),
+        Err(err) => println!("Ignoring invalid odds rules {:?}: {}", path, err),
+    }
+    rules
+}
+
+/// The built-in rules with the user's `odds.toml` applied on top.
+pub fn load_rules() -> OddsRules {
+    match odds_rules_path() {
+        Some(path) => load_rules_from(&path),
+        None => default_rules(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use float_cmp::approx_eq;
+
+    use super::{default_rules, load_rules_from, parse_rules, Formula, Progress};
+    use crate::utils::db_handler::{HuntSettings, HuntStatus, Pokemon};
+
+    fn methods(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn at_chain(chain: u32) -> Progress {
+        Progress { chain, ..Default::default() }
+    }
+
+    #[test]
+    fn test_default_rules_parse() {
+        let rules = default_rules();
+        assert!(rules.game("gen3").unwrap().methods.is_empty());
+        assert_eq!(Formula::Additive, rules.game("gen6").unwrap().formula);
+        assert_eq!(Formula::Rolls, rules.game("pla").unwrap().formula);
+    }
+
+    #[test]
+    fn test_each_game_has_its_own_methods() {
+        let rules = default_rules();
+        assert!(rules.game("swsh").unwrap().method("dynamax").is_some());
+        assert!(rules.game("swsh").unwrap().method("mass").is_none());
+        assert!(rules.game("pla").unwrap().method("masuda").is_none());
+        assert!(rules.game("bdsp").unwrap().method("underground").is_some());
+        assert_eq!(vec!["Sparkling Power", "Outbreak"], rules.game("sv").unwrap().groups());
+    }
+
+    #[test]
+    fn test_chain_steps() {
+        let rules = default_rules();
+        let gen7 = rules.game("gen7").unwrap();
+        let sos = methods(&["sos"]);
+        assert!(gen7.uses_chain(&sos));
+        assert!(!gen7.uses_chain(&methods(&["charm"])));
+        assert!(approx_eq!(f64, 1.0 / 4096.0, gen7.probability(&sos, at_chain(10)), ulps = 2));
+        assert!(approx_eq!(f64, 5.0 / 4096.0, gen7.probability(&sos, at_chain(11)), ulps = 2));
+        assert!(approx_eq!(f64, 13.0 / 4096.0, gen7.probability(&sos, at_chain(255)), ulps = 2));
+    }
+
+    #[test]
+    fn test_radar_follows_patch_odds() {
+        let rules = default_rules();
+        let gen4 = rules.game("gen4").unwrap();
+        let radar = methods(&["radar"]);
+        for chain in 0..=40u32 {
+            let patch = (65535.0 / (8200.0 - 200.0 * chain as f64)).ceil() / 65536.0;
+            assert!(approx_eq!(f64, patch, gen4.probability(&radar, at_chain(chain)), ulps = 2), "chain {}", chain);
+        }
+        assert!(approx_eq!(f64, 41.0 / 8192.0, gen4.probability(&radar, at_chain(100)), ulps = 2));
+        let gen6 = rules.game("gen6").unwrap();
+        assert!(approx_eq!(f64, 43.0 / 4096.0, gen6.probability(&methods(&["radar", "charm"]), at_chain(40)), ulps = 2));
+    }
+
+    #[test]
+    fn test_dexnav_search_level_chance() {
+        let rules = default_rules();
+        let search = rules.game("gen6").unwrap().method("dexnav").unwrap().search_level.as_ref().unwrap();
+        // 6 per level up to 100, 2 per level up to 200, 1 per level after that, in 1/10000.
+        assert!(approx_eq!(f64, 0.0, search.chance(0), ulps = 2));
+        assert!(approx_eq!(f64, 0.03, search.chance(50), ulps = 2));
+        assert!(approx_eq!(f64, 0.06, search.chance(100), ulps = 2));
+        assert!(approx_eq!(f64, 0.07, search.chance(150), ulps = 2));
+        assert!(approx_eq!(f64, 0.08, search.chance(200), ulps = 2));
+        assert!(approx_eq!(f64, 0.1599, search.chance(999), ulps = 2));
+    }
+
+    #[test]
+    fn test_dexnav_odds() {
+        let rules = default_rules();
+        let gen6 = rules.game("gen6").unwrap();
+        let dexnav = methods(&["dexnav"]);
+        let at = |chain, search_level| Progress { chain, search_level };
+        assert!(approx_eq!(f64, 1.0 / 4096.0, gen6.probability(&dexnav, at(0, 0)), ulps = 2));
+        assert!(approx_eq!(f64, 1.24 / 4096.0, gen6.probability(&dexnav, at(0, 100)), ulps = 2));
+        // The 50th and 100th encounters of a chain get 5 and 10 extra rolls.
+        assert!(approx_eq!(f64, 1.0 / 4096.0, gen6.probability(&dexnav, at(49, 0)), ulps = 2));
+        assert!(approx_eq!(f64, 6.0 / 4096.0, gen6.probability(&dexnav, at(50, 0)), ulps = 2));
+        assert!(approx_eq!(f64, 11.0 / 4096.0, gen6.probability(&dexnav, at(100, 0)), ulps = 2));
+        assert!(approx_eq!(f64, 6.0 / 4096.0, gen6.probability(&dexnav, at(150, 0)), ulps = 2));
+        let with_charm = methods(&["dexnav", "charm"]);
+        assert!(approx_eq!(f64, (13.0 + 4.0 * 0.1599) / 4096.0, gen6.probability(&with_charm, at(100, 999)), ulps = 4));
+    }
+
+    #[test]
+    fn test_unsorted_chain_is_rejected() {
+        let text = "[[game]]\nid = \"a\"\nname = \"A\"\nbase_odds = 10\n\
+                    [[game.method]]\nid = \"x\"\nname = \"X\"\nchain = [[5, 1], [2, 0]]";
+        assert!(parse_rules(text).is_err());
+    }
+
+    #[test]
+    fn test_gen2_shiny_parent_replaces_base_odds() {
+        let rules = default_rules();
+        let gen2 = rules.game("gen2").unwrap();
+        assert!(approx_eq!(f64, 1.0 / 8192.0, gen2.probability(&[], Progress::default()), ulps = 2));
+        assert!(approx_eq!(f64, 1.0 / 64.0, gen2.probability(&methods(&["shiny_parent"]), Progress::default()), ulps = 2));
+    }
+
+    #[test]
+    fn test_lets_go_combo() {
+        let rules = default_rules();
+        let lgpe = rules.game("lgpe").unwrap();
+        let enabled = methods(&["charm", "lure", "combo"]);
+        assert!(approx_eq!(f64, 4.0 / 4096.0, lgpe.probability(&enabled, Progress { chain: 10, search_level: 0 }), ulps = 2));
+        assert!(approx_eq!(f64, 7.0 / 4096.0, lgpe.probability(&enabled, Progress { chain: 11, search_level: 0 }), ulps = 2));
+        assert!(approx_eq!(f64, 15.0 / 4096.0, lgpe.probability(&enabled, Progress { chain: 31, search_level: 0 }), ulps = 2));
+    }
+
+    /// One in how many encounters, rounded to a tenth like the published tables.
+    fn one_in(p: f64) -> f64 {
+        (10.0 / p).round() / 10.0
+    }
+
+    #[test]
+    fn test_pla_reference_odds() {
+        let rules = default_rules();
+        let pla = rules.game("pla").unwrap();
+        let odds = |ids: &[&str]| one_in(pla.probability(&methods(ids), Progress::default()));
+        assert_eq!(4096.0, odds(&[]));
+        assert_eq!(2048.3, odds(&["dex10"]));
+        assert_eq!(1024.4, odds(&["dexmax"]));
+        assert_eq!(585.6, odds(&["dexmax", "charm"]));
+        assert_eq!(158.0, odds(&["mass"]));
+        assert_eq!(128.5, odds(&["mass", "dexmax", "charm"]));
+        assert_eq!(315.5, odds(&["massive"]));
+        assert_eq!(216.1, odds(&["massive", "dexmax", "charm"]));
+    }
+
+    #[test]
+    fn test_pla_outbreak_probability() {
+        let rules = default_rules();
+        let pla = rules.game("pla").unwrap();
+        let enabled = methods(&["mass", "dexmax", "charm"]);
+        assert_eq!(Some(12), pla.outbreak_spawns(&enabled));
+        let per_spawn = 1.0 - (4095.0f64 / 4096.0).powi(32);
+        let expected = 1.0 - (1.0 - per_spawn).powi(12);
+        assert!(approx_eq!(f64, expected, pla.outbreak_probability(&enabled, Progress::default()).unwrap(), epsilon = 1e-12));
+        assert_eq!(None, pla.outbreak_probability(&methods(&["charm"]), Progress::default()));
+        assert_eq!(Some(9), pla.outbreak_spawns(&methods(&["massive"])));
+    }
+
+    #[test]
+    fn test_sv_reference_odds() {
+        let rules = default_rules();
+        let sv = rules.game("sv").unwrap();
+        let odds = |ids: &[&str]| one_in(sv.probability(&methods(ids), Progress::default()));
+        assert_eq!(4096.0, odds(&[]));
+        assert_eq!(1365.7, odds(&["charm"]));
+        assert_eq!(1024.4, odds(&["sparkling3"]));
+        assert_eq!(819.6, odds(&["outbreak60", "charm"]));
+        assert_eq!(512.4, odds(&["outbreak60", "sparkling3", "charm"]));
+    }
+
+    #[test]
+    fn test_sv_sandwich_and_outbreak() {
+        let rules = default_rules();
+        let sv = rules.game("sv").unwrap();
+        let mut enabled = methods(&["charm"]);
+        sv.toggle(&mut enabled, "sparkling3", true);
+        sv.toggle(&mut enabled, "outbreak60", true);
+        assert_eq!(8.0, sv.rolls(&enabled, 0));
+
+        // Only one level of a group applies.
+        sv.toggle(&mut enabled, "sparkling1", true);
+        assert_eq!(methods(&["charm", "outbreak60", "sparkling1"]), enabled);
+    }
+
+    #[test]
+    fn test_additive_odds() {
+        let rules = default_rules();
+        let gen5 = rules.game("gen5").unwrap();
+        assert!(approx_eq!(f64, 1.0 / 8192.0, gen5.probability(&[], Progress::default()), ulps = 2));
+        assert!(approx_eq!(f64, 8.0 / 8192.0, gen5.probability(&methods(&["masuda", "charm"]), Progress::default()), ulps = 2));
+        let gen6 = rules.game("gen6").unwrap();
+        assert!(approx_eq!(f64, 7.0 / 4096.0, gen6.probability(&methods(&["safari", "charm"]), Progress::default()), ulps = 2));
+    }
+
+    #[test]
+    fn test_unknown_methods_are_ignored() {
+        let rules = default_rules();
+        assert!(approx_eq!(f64, 1.0, rules.game("gen4").unwrap().rolls(&methods(&["charm"]), 0), ulps = 2));
+    }
+
+    #[test]
+    fn test_toggle_drops_conflicts() {
+        let rules = default_rules();
+        let gen6 = rules.game("gen6").unwrap();
+        let mut enabled = methods(&["charm", "radar"]);
+        gen6.toggle(&mut enabled, "masuda", true);
+        assert_eq!(methods(&["charm", "masuda"]), enabled);
+        gen6.toggle(&mut enabled, "charm", false);
+        assert_eq!(methods(&["masuda"]), enabled);
+    }
+
+    #[test]
+    fn test_rolls_formula() {
+        let rules = parse_rules(
+            "[[game]]
+             id = \"test\"
+             name = \"Test\"
+             base_odds = 4096
+             formula = \"rolls\"
+             [[game.method]]
+             id = \"charm\"
+             name = \"Shiny Charm\"
+             bonus = 2").unwrap();
+        let game = rules.game("test").unwrap();
+        let expected = 1.0 - (4095.0f64 / 4096.0).powi(3);
+        assert!(approx_eq!(f64, expected, game.probability(&methods(&["charm"]), Progress::default()), ulps = 2));
+    }
+
+    #[test]
+    fn test_hunt_probability_uses_saved_settings() {
+        let rules = default_rules();
+        let mut pkmn = Pokemon {
+            id: 1,
+            name: "ralts".to_owned(),
+            counter: 40,
+            chain: 40,
+            status: HuntStatus::Active,
+            step: 1,
+            linked: false,
+            result: None,
+            settings: HuntSettings { game: Some("gen5".to_owned()), methods: methods(&["charm"]), search_level: 0 },
+        };
+        assert!(approx_eq!(f64, 3.0 / 8192.0, rules.hunt_probability(&pkmn).unwrap(), ulps = 2));
+        pkmn.settings.game = None;
+        assert_eq!(None, rules.hunt_probability(&pkmn));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        assert!(parse_rules("[[game]]\nid = \"a\"\nname = \"A\"\nbase_odds = 0").is_err());
+        let unknown = "[[game]]\nid = \"a\"\nname = \"A\"\nbase_odds = 10\n\
+                       [[game.method]]\nid = \"x\"\nname = \"X\"\nbonus = 1\nexcludes = [\"y\"]";
+        assert!(parse_rules(unknown).is_err());
+    }
+
+    #[test]
+    fn test_override_file_replaces_and_adds_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("odds.toml");
+        fs::write(&path, "[[game]]\nid = \"gen5\"\nname = \"Fixed\"\nbase_odds = 100\n\
+                          [[game]]\nid = \"new\"\nname = \"New\"\nbase_odds = 10").unwrap();
+        let rules = load_rules_from(&path);
+        assert_eq!("Fixed", rules.game("gen5").unwrap().name);
+        assert!(rules.game("gen5").unwrap().methods.is_empty());
+        assert_eq!("New", rules.games.last().unwrap().name);
+        assert_eq!(default_rules().games.len() + 1, rules.games.len());
+    }
+
+    #[test]
+    fn test_invalid_override_file_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("odds.toml");
+        fs::write(&path, "not toml [").unwrap();
+        assert_eq!(default_rules(), load_rules_from(&path));
+        assert_eq!(default_rules(), load_rules_from(&dir.path().join("missing.toml")));
+    }
+}
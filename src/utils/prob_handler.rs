@@ -11,6 +11,78 @@ pub fn convert_to_percentage(x:f64) -> f64 {
     x * 100.0
 }
 
+/// Mean number of encounters until the first shiny.
+pub fn expected_encounters(p: f64) -> f64 {
+    1.0 / p
+}
+
+/// Smallest number of encounters with at least a 50% chance of a shiny.
+pub fn median_encounters(p: f64) -> i32 {
+    encounters_for_chance(0.5, p)
+}
+
+/// Smallest number of encounters with at least an `x` chance of a shiny.
+pub fn encounters_for_chance(x: f64, p: f64) -> i32 {
+    if p >= 1.0 {
+        return 1;
+    }
+    log_with_base(1.0-p, 1.0-x).ceil().max(1.0) as i32
+}
+
+/// Share of hunters with the same odds that would have found a shiny within `n` encounters.
+/// Past the median this is how many hunters you are unluckier than.
+pub fn luck_percentile(n: i32, p: f64) -> f64 {
+    probability_within_n_tries(n, p)
+}
+
+/// Chance of at least `k` shinies in `n` encounters.
+pub fn probability_at_least_k(k: i32, n: i32, p: f64) -> f64 {
+    if k <= 0 {
+        return 1.0;
+    }
+    if k > n {
+        return 0.0;
+    }
+    if p >= 1.0 {
+        return 1.0;
+    }
+    // Sums the binomial terms below k, each one derived from the previous.
+    let mut term = (1.0-p).powf(n.into());
+    let mut below = term;
+    for i in 0..k-1 {
+        term *= (n - i) as f64 / (i + 1) as f64 * p / (1.0-p);
+        below += term;
+    }
+    (1.0 - below).clamp(0.0, 1.0)
+}
+
+/// Chance of a shiny within `n` encounters when the odds changed during the hunt.
+/// `changes` holds `(encounter the odds started at, odds)` sorted by encounter,
+/// encounters before the first change use its odds.
+pub fn probability_with_changing_odds(changes: &[(i32, f64)], n: i32) -> f64 {
+    let mut miss = 1.0;
+    for (i, (start, p)) in changes.iter().enumerate() {
+        let start = if i == 0 { 0 } else { (*start).min(n) };
+        let end = changes.get(i + 1).map_or(n, |(next, _)| (*next).min(n));
+        if end > start {
+            miss *= (1.0-p).powf((end - start).into());
+        }
+    }
+    1.0 - miss
+}
+
+/// `samples + 1` evenly spaced points `(encounters, chance)` of [`probability_with_changing_odds`]
+/// from 0 to `max_n` encounters, for plotting.
+pub fn chance_curve(changes: &[(i32, f64)], max_n: i32, samples: i32) -> Vec<(i32, f64)> {
+    let samples = samples.clamp(1, max_n.max(1));
+    (0..=samples)
+        .map(|i| {
+            let n = (max_n as i64 * i as i64 / samples as i64) as i32;
+            (n, probability_with_changing_odds(changes, n))
+        })
+        .collect()
+}
+
 fn log_with_base(base: f64, x: f64) -> f64 {
     f64::ln(x) / f64::ln(base)
 }
@@ -19,7 +91,11 @@ fn log_with_base(base: f64, x: f64) -> f64 {
 mod tests {
     use float_cmp::approx_eq;
 
-    use super::{log_with_base, probability_within_n_tries, num_tries_for_x_percent_chance};
+    use super::{
+        log_with_base, probability_within_n_tries, num_tries_for_x_percent_chance, expected_encounters,
+        median_encounters, encounters_for_chance, luck_percentile, probability_at_least_k,
+        probability_with_changing_odds, chance_curve,
+    };
 
     #[test]
     fn test_log_with_base() {
@@ -114,4 +190,75 @@ mod tests {
         println!("result: {}", res);
         assert_eq!(n, res);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_expected_encounters() {
+        assert!(approx_eq!(f64, 4096.0, expected_encounters(1.0 / 4096.0), epsilon=0.00001));
+        assert!(approx_eq!(f64, 1365.33333, expected_encounters(3.0 / 4096.0), epsilon=0.00001));
+    }
+
+    #[test]
+    fn test_median_encounters() {
+        // ln(0.5) / ln(4095/4096) = 2838.78
+        assert_eq!(2839, median_encounters(1.0 / 4096.0));
+        // ln(0.5) / ln(8191/8192) = 5677.92
+        assert_eq!(5678, median_encounters(1.0 / 8192.0));
+        assert!(probability_within_n_tries(2839, 1.0 / 4096.0) >= 0.5);
+        assert!(probability_within_n_tries(2838, 1.0 / 4096.0) < 0.5);
+    }
+
+    #[test]
+    fn test_encounters_for_chance_edges() {
+        assert_eq!(1, encounters_for_chance(0.9, 1.0));
+        assert_eq!(1, encounters_for_chance(0.0, 1.0 / 4096.0));
+        // ln(0.1) / ln(4095/4096) = 9430.2
+        assert_eq!(9431, encounters_for_chance(0.9, 1.0 / 4096.0));
+    }
+
+    #[test]
+    fn test_luck_percentile() {
+        // 1 - (4095/4096)^7000 = 0.81898
+        assert!(approx_eq!(f64, 0.81898, luck_percentile(7000, 1.0 / 4096.0), epsilon=0.00001));
+        assert!(approx_eq!(f64, 0.0, luck_percentile(0, 1.0 / 4096.0), epsilon=0.00001));
+    }
+
+    #[test]
+    fn test_probability_at_least_k() {
+        let p = 1.0 / 4096.0;
+        let n = 4096;
+        assert!(approx_eq!(f64, probability_within_n_tries(n, p), probability_at_least_k(1, n, p), epsilon=0.0000001));
+        // 1 - P(0) - P(1) for Binomial(4096, 1/4096) = 0.26424
+        assert!(approx_eq!(f64, 0.26424, probability_at_least_k(2, n, p), epsilon=0.00001));
+        // 1 - P(0) - P(1) - P(2) = 0.08028
+        assert!(approx_eq!(f64, 0.08028, probability_at_least_k(3, n, p), epsilon=0.00001));
+        assert!(approx_eq!(f64, 1.0, probability_at_least_k(0, n, p), epsilon=0.00001));
+        assert!(approx_eq!(f64, 0.0, probability_at_least_k(5, 4, p), epsilon=0.00001));
+        assert!(approx_eq!(f64, 0.25, probability_at_least_k(2, 2, 0.5), epsilon=0.00001));
+    }
+
+    #[test]
+    fn test_probability_with_changing_odds() {
+        let p = 1.0 / 4096.0;
+        let same = probability_with_changing_odds(&[(0, p)], 1000);
+        assert!(approx_eq!(f64, probability_within_n_tries(1000, p), same, epsilon=0.0000001));
+
+        // 1000 encounters at 1/4096, then 500 at 3/4096.
+        let changes = [(0, p), (1000, 3.0 * p)];
+        let exp = 1.0 - (1.0 - p).powi(1000) * (1.0 - 3.0 * p).powi(500);
+        assert!(approx_eq!(f64, exp, probability_with_changing_odds(&changes, 1500), epsilon=0.0000001));
+        // Changes after the current count don't count yet.
+        assert!(approx_eq!(f64, probability_within_n_tries(800, p), probability_with_changing_odds(&changes, 800), epsilon=0.0000001));
+        assert!(approx_eq!(f64, 0.0, probability_with_changing_odds(&[], 800), epsilon=0.0000001));
+    }
+
+    #[test]
+    fn test_chance_curve() {
+        let p = 1.0 / 4096.0;
+        let curve = chance_curve(&[(0, p)], 1000, 4);
+        assert_eq!(vec![0, 250, 500, 750, 1000], curve.iter().map(|(n, _)| *n).collect::<Vec<_>>());
+        assert!(approx_eq!(f64, 0.0, curve[0].1, epsilon=0.0000001));
+        assert!(approx_eq!(f64, probability_within_n_tries(750, p), curve[3].1, epsilon=0.0000001));
+        // Never more points than encounters.
+        assert_eq!(3, chance_curve(&[(0, p)], 2, 200).len());
+    }
+}